@@ -0,0 +1,16 @@
+-- Everything uploaded so far has been a PNG except for one JPEG, which used
+-- to be special-cased in the site code.
+alter table post_images
+    add column media_type text not null default 'image/png',
+    add column extension text not null default 'png';
+
+update post_images
+    set media_type = 'image/jpeg', extension = 'jpg'
+    from posts
+    where post_images.post_id = posts.id
+        and posts.slug = 'a-bubble-blower-very-cool'
+        and post_images."order" = 2;
+
+alter table post_images
+    alter column media_type drop default,
+    alter column extension drop default;
@@ -0,0 +1,18 @@
+-- Posts can now have audio, video, and downloadable files as well as images.
+alter table post_images rename to post_files;
+alter sequence post_images_id_seq rename to post_files_id_seq;
+alter index post_images_pkey rename to post_files_pkey;
+alter index post_images_post_id_order_key rename to post_files_post_id_order_key;
+alter table post_files
+    rename constraint post_images_post_id_fkey to post_files_post_id_fkey;
+
+-- Everything uploaded so far is an image.  File sizes are only shown for
+-- downloads, so it's fine for the existing images to be left at 0.
+alter table post_files
+    add column kind text not null default 'image'
+        check (kind in ('image', 'audio', 'video', 'download')),
+    add column size bigint not null default 0;
+
+alter table post_files
+    alter column kind drop default,
+    alter column size drop default;
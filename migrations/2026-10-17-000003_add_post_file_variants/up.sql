@@ -0,0 +1,18 @@
+-- Dimensions of the original file; null for anything that isn't an image (or
+-- for images uploaded before this was recorded)
+alter table post_files
+    add column width integer,
+    add column height integer;
+
+-- Smaller copies of full-size images in modern formats, for srcset
+create table post_file_variants (
+    id serial primary key,
+    post_file_id integer not null,
+    media_type text not null,
+    extension text not null,
+    width integer not null,
+    height integer not null,
+
+    foreign key (post_file_id) references post_files (id) on delete cascade,
+    unique (post_file_id, media_type, width)
+);
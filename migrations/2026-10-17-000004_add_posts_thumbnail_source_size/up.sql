@@ -0,0 +1,7 @@
+-- The dimensions of the image a post's thumbnails are made from.  Thumbnails
+-- are scaled to a fixed height, so this is enough to work out the width of a
+-- thumbnail at any height.  Existing posts are filled in by
+-- `cem-cli backfill-dimensions`.
+alter table posts
+    add column thumbnail_source_width integer,
+    add column thumbnail_source_height integer;
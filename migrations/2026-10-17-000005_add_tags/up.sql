@@ -0,0 +1,16 @@
+create table tags (
+    id serial primary key,
+    name text not null,
+    slug text not null,
+
+    unique (slug)
+);
+
+create table post_tags (
+    post_id integer not null,
+    tag_id integer not null,
+
+    primary key (post_id, tag_id),
+    foreign key (post_id) references posts (id) on delete cascade,
+    foreign key (tag_id) references tags (id)
+);
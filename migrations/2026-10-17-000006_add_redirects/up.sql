@@ -0,0 +1,6 @@
+create table redirects (
+    path text primary key,
+    post_id integer not null,
+
+    foreign key (post_id) references posts (id) on delete cascade
+);
@@ -0,0 +1,6 @@
+-- Redirects can now point at directories as well as posts, but never both.
+alter table redirects
+    alter column post_id drop not null,
+    add column directory_id integer
+        references directories (id) on delete cascade,
+    add check (num_nonnulls(post_id, directory_id) = 1);
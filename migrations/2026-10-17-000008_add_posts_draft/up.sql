@@ -0,0 +1,9 @@
+-- Drafts are hidden everywhere on the site except at their preview URL.
+-- Posts with a timestamp in the future are hidden the same way until that
+-- time comes, so "scheduled" doesn't need a column of its own.
+alter table posts
+    add column draft boolean not null default false,
+    add column preview_token text not null
+        default replace(gen_random_uuid()::text, '-', '');
+
+create unique index on posts (preview_token);
@@ -0,0 +1,3 @@
+-- Unlisted posts are public, but only to people who have the link; they're
+-- left out of every listing, feed, and prev/next link.
+alter table posts add column unlisted boolean not null default false;
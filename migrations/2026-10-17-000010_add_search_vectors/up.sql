@@ -0,0 +1,17 @@
+-- Full-text search for /search.  Generated columns can't look at other
+-- tables, so alt text gets its own vector on post_files and the two are
+-- combined at query time.  Titles are weighted above descriptions, and alt
+-- text is left at the lowest weight.
+alter table posts
+    add column search_vector tsvector not null generated always as (
+        setweight(to_tsvector('english', title), 'A')
+            || setweight(to_tsvector('english', description), 'B')
+    ) stored;
+
+alter table post_files
+    add column search_vector tsvector not null generated always as (
+        to_tsvector('english', alt_text)
+    ) stored;
+
+create index on posts using gin (search_vector);
+create index on post_files using gin (search_vector);
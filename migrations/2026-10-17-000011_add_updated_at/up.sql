@@ -0,0 +1,20 @@
+-- When each row was last changed, kept up to date by Diesel's trigger.  This
+-- one is timestamp with time zone from the start, since the trigger sets it
+-- from current_timestamp and a plain timestamp would get the server's local
+-- time.
+alter table posts
+    add column updated_at timestamp with time zone not null default now();
+alter table directories
+    add column updated_at timestamp with time zone not null default now();
+alter table post_files
+    add column updated_at timestamp with time zone not null default now();
+
+-- Nothing's been edited as far as we know, so existing posts and their files
+-- count as last changed when they went up
+update posts set updated_at = timestamp at time zone 'UTC';
+update post_files set updated_at = posts.updated_at
+    from posts where posts.id = post_files.post_id;
+
+select diesel_manage_updated_at('posts');
+select diesel_manage_updated_at('directories');
+select diesel_manage_updated_at('post_files');
@@ -0,0 +1,11 @@
+-- Finally doing what add_timestamp_default put off.  Existing timestamps were
+-- all stored in UTC, so they're converted as such.
+--
+-- From now on, the offset from UTC (in seconds) each timestamp was entered
+-- with is kept too, so `cem-cli post-edit` can show it the same way.
+-- Existing posts have none, and are shown in the site's display time zone.
+alter table posts
+    alter column timestamp type timestamp with time zone
+        using timestamp at time zone 'UTC',
+    alter column timestamp set default now(),
+    add column timestamp_offset integer;
@@ -0,0 +1,7 @@
+-- Post files are updated in place when a post is edited, so that they keep
+-- their IDs.  Reordering them means two files can briefly share an order, so
+-- the check waits until the end of the transaction.
+alter table post_files
+    drop constraint post_files_post_id_order_key,
+    add constraint post_files_post_id_order_key unique (post_id, "order")
+        deferrable initially deferred;
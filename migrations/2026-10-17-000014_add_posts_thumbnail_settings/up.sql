@@ -0,0 +1,26 @@
+-- Where a post's thumbnails come from, if not its first image: another of its
+-- files (by order), or a separate image that's only used for thumbnails and
+-- saved as `thumbnail.<extension>` alongside the post's files.  Thumbnails can
+-- also be cropped to a rectangle, or to a sensible shape around a focal point,
+-- both in pixels of the source image.
+alter table posts
+    add column thumbnail_file integer,
+    add column thumbnail_extension text,
+    add column thumbnail_crop_x integer,
+    add column thumbnail_crop_y integer,
+    add column thumbnail_crop_width integer,
+    add column thumbnail_crop_height integer,
+    add column thumbnail_focus_x integer,
+    add column thumbnail_focus_y integer,
+    add constraint posts_thumbnail_source_check
+        check (thumbnail_file is null or thumbnail_extension is null),
+    add constraint posts_thumbnail_crop_check check (
+        num_nulls(
+            thumbnail_crop_x,
+            thumbnail_crop_y,
+            thumbnail_crop_width,
+            thumbnail_crop_height
+        ) in (0, 4)
+        and num_nulls(thumbnail_focus_x, thumbnail_focus_y) in (0, 2)
+        and (thumbnail_crop_x is null or thumbnail_focus_x is null)
+    );
@@ -0,0 +1,10 @@
+-- Saves whose changes have been committed, but whose staged files might not
+-- have been swapped into place yet.  Each row is written in the same
+-- transaction as its post and removed once the files are in place, so after
+-- a crash `cem-cli clean-tmp` can tell a save that went through (and finish
+-- it) from one that didn't (and throw it away).
+create table staged_saves (
+    -- The staging directory's name, within the upload directory's `tmp`
+    staging_dir text primary key,
+    post_id integer not null references posts (id) on delete cascade
+);
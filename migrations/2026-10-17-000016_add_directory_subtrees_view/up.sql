@@ -0,0 +1,16 @@
+-- Every directory paired with itself and each directory beneath it, for
+-- picking out everything in a subtree.  Matching on `directory_paths` instead
+-- would mean a `like` on paths, which goes wrong for slugs containing `_` or
+-- `%`.  This isn't materialized, so it never needs refreshing; there aren't
+-- enough directories for walking them all each time to matter.
+create view directory_subtrees (directory_id, descendant_id) as
+    with recursive subtrees (directory_id, descendant_id) as (
+        select id, id
+            from directories
+        union all
+        select subtrees.directory_id, directories.id
+            from directories
+            join subtrees
+                on directories.parent_directory_id = subtrees.descendant_id
+    )
+    select * from subtrees;
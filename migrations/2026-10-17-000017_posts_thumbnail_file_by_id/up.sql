@@ -0,0 +1,25 @@
+-- Refer to the file a post's thumbnails come from by its ID rather than its
+-- position, so it stays the same file when files are moved around.  If it's
+-- deleted, thumbnails go back to coming from the first image.  Checking the
+-- key waits until the end of the transaction, so a post can be saved along
+-- with a new file it takes its thumbnails from.
+alter table posts
+    add column thumbnail_file_id integer
+        references post_files (id)
+            on delete set null
+            deferrable initially deferred;
+
+-- The thumbnail hasn't changed, just how it's stored, so this isn't an edit
+alter table posts disable trigger set_updated_at;
+update posts
+    set thumbnail_file_id = post_files.id
+    from post_files
+    where post_files.post_id = posts.id
+        and post_files."order" = posts.thumbnail_file;
+alter table posts enable trigger set_updated_at;
+
+alter table posts
+    drop constraint posts_thumbnail_source_check,
+    drop column thumbnail_file,
+    add constraint posts_thumbnail_source_check
+        check (thumbnail_file_id is null or thumbnail_extension is null);
@@ -3,7 +3,7 @@
 use std::default::Default;
 use std::error::Error;
 use std::fmt::Write as _;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use clap::Parser as _;
 use diesel::prelude::*;
@@ -21,9 +21,60 @@ struct CLI {
 #[derive(Debug, clap::Subcommand)]
 enum Command {
     /// Create a new post.
-    PostNew,
+    PostNew {
+        /// Read the post from a TOML file ("-" for stdin) instead of opening
+        /// an editor, and report any errors as JSON.  Relative local_paths are
+        /// relative to the file (or to the current directory, for stdin).
+        #[arg(long)]
+        from: Option<PathBuf>,
+    },
     /// Edit an existing post.
-    PostEdit { path: String },
+    PostEdit {
+        path: String,
+        /// Read the edited post from a TOML file ("-" for stdin) instead of
+        /// opening an editor, and report any errors as JSON.  Relative
+        /// local_paths are relative to the file (or to the current directory,
+        /// for stdin).
+        #[arg(long)]
+        from: Option<PathBuf>,
+    },
+    /// Print an existing post as TOML, in the form post-edit takes.
+    PostShow { path: String },
+    /// Delete a post and all its files.
+    PostDelete { path: String },
+    /// Move a post to a new path, redirecting the old one.
+    PostMove { path: String, new_path: String },
+    /// Create a new directory.
+    DirNew,
+    /// Edit an existing directory.
+    DirEdit { path: String },
+    /// Move a directory (and everything in it) into another directory, or to
+    /// the top level if the new parent is "/".
+    DirMove { path: String, parent: String },
+    /// Delete a directory.
+    DirDelete {
+        path: String,
+        /// Also delete all the posts and subdirectories in it
+        #[arg(long)]
+        recursive: bool,
+    },
+    /// Record the sizes of files, and the dimensions of images, uploaded
+    /// before they were tracked.
+    BackfillDimensions,
+    /// Generate the smaller variants of images uploaded before variants were
+    /// made.
+    BackfillVariants,
+    /// Clean up after saves that crashed partway through, finishing or undoing
+    /// any half-swapped file directories.  Don't run this while saving.
+    CleanTmp,
+    /// Check that the database and the upload directory agree, and exit with
+    /// an error if they don't.
+    Check {
+        /// Regenerate missing thumbnails, remove orphaned directories, and
+        /// refresh stale views.  Don't use this while saving.
+        #[arg(long)]
+        fix: bool,
+    },
 }
 
 /// A bundle of arguments that need to get passed around everywhere in the
@@ -32,6 +83,9 @@ struct PostContext<'a> {
     post_id: Option<i32>,
     connection: &'a mut diesel::PgConnection,
     config: cem::CEMConfig,
+    /// What relative local_paths are relative to; empty for the current
+    /// directory
+    base_dir: PathBuf,
 }
 
 /// A post, as edited in TOML form (as part of EditPostWithFiles)
@@ -57,24 +111,323 @@ struct EditPost {
         default
     )]
     #[diesel(
-        select_expression = db::posts::timestamp.nullable(),
-        select_expression_type = diesel::dsl::Nullable<db::posts::timestamp>
+        select_expression = (db::posts::timestamp, db::posts::timestamp_offset),
+        select_expression_type =
+            (db::posts::timestamp, db::posts::timestamp_offset),
+        deserialize_as = StoredTimestamp
     )]
-    pub timestamp: Option<chrono::NaiveDateTime>,
+    pub timestamp: Option<chrono::DateTime<chrono::FixedOffset>>,
+    /// Not a column either; only `draft` is stored, and scheduled posts are
+    /// just ones with a timestamp in the future
+    #[serde(default)]
+    #[diesel(
+        select_expression = diesel::dsl::sql::<diesel::sql_types::Text>(
+            "case
+                when posts.draft then 'draft'
+                when posts.timestamp > now()
+                    then 'scheduled'
+                else 'published'
+            end"
+        ),
+        select_expression_type =
+            diesel::expression::SqlLiteral<diesel::sql_types::Text>,
+        deserialize_as = String
+    )]
+    state: PostState,
+    /// Left out of listings, feeds, and prev/next links, but still public
+    #[serde(default)]
+    unlisted: bool,
     description: String,
+    /// Tag names, e.g. `["Trinket", "Watercolour"]`
+    // Not a column, so it gets aggregated up from post_tags
+    #[serde(default)]
+    #[diesel(
+        select_expression = diesel::dsl::sql::<
+            diesel::sql_types::Array<diesel::sql_types::Text>,
+        >(
+            "coalesce((
+                select array_agg(tags.name order by tags.name)
+                from post_tags join tags on tags.id = post_tags.tag_id
+                where post_tags.post_id = posts.id
+            ), '{}')"
+        ),
+        select_expression_type = diesel::expression::SqlLiteral<
+            diesel::sql_types::Array<diesel::sql_types::Text>,
+        >
+    )]
+    tags: Vec<String>,
+}
+
+/// A directory, as edited in TOML form
+#[derive(
+    Default,
+    diesel::Queryable,
+    diesel::Selectable,
+    serde::Deserialize,
+    serde::Serialize,
+)]
+#[diesel(table_name = db::directories)]
+struct EditDirectory {
+    #[diesel(
+        select_expression = db::directory_paths::path,
+        select_expression_type = db::directory_paths::path
+    )]
+    path: String,
+    title: String,
+    has_proper_title: bool,
+}
+
+/// A bundle of arguments that need to get passed around in the course of
+/// editing or creating a directory.
+struct DirectoryContext<'a> {
+    directory_id: Option<i32>,
+    connection: &'a mut diesel::PgConnection,
 }
 
 /// A post file, as edited in TOML form (as part of EditPostWithFiles)
+///
+/// Existing files are identified by `id`, so they can be moved around in the
+/// list, replaced by giving a `local_path`, or deleted by removing them from
+/// the list.  New files have no `id`.
 #[derive(Default, serde::Deserialize, serde::Serialize)]
 struct EditPostFile {
+    id: Option<i32>,
     local_path: Option<PathBuf>,
+    /// Left out to have it guessed from the file's type
+    kind: Option<FileKind>,
     alt_text: String,
+    /// Set on the image to make the post's thumbnails from, if not the first
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    thumbnail: bool,
+}
+
+/// Where a post's thumbnails come from, as edited in TOML form (as part of
+/// EditPostWithFiles)
+///
+/// Left empty, they're made from the post's first image (or the one marked
+/// `thumbnail = true` in `files`), uncropped.  Thumbnails can't be made from
+/// AVIF, so posts that start with one have to choose something else.
+#[derive(Default, serde::Deserialize, serde::Serialize)]
+struct EditThumbnail {
+    /// A separate image to use instead, which isn't shown with the post
+    local_path: Option<PathBuf>,
+    /// Set when the post already has a separate thumbnail image; leave it set
+    /// to keep the image
+    #[serde(default)]
+    separate_image: bool,
+    /// A rectangle to crop the image to, in pixels, e.g.
+    /// `{ x = 0, y = 0, width = 800, height = 600 }`
+    crop: Option<cem::thumbnails::Crop>,
+    /// A point to keep in the middle of the thumbnail, in pixels, when the
+    /// image is too tall or too wide to use as it is, e.g.
+    /// `{ x = 400, y = 300 }`
+    focus: Option<cem::thumbnails::Focus>,
+}
+
+impl EditThumbnail {
+    /// The thumbnail settings an existing post has now.
+    fn from_post(post: &db::Post) -> Self {
+        use cem::thumbnails::Framing;
+
+        let framing = post.thumbnail_framing();
+
+        EditThumbnail {
+            local_path: None,
+            separate_image: post.thumbnail_extension.is_some(),
+            crop: match framing {
+                Framing::Crop(crop) => Some(crop),
+                _ => None,
+            },
+            focus: match framing {
+                Framing::Focus(focus) => Some(focus),
+                _ => None,
+            },
+        }
+    }
+
+    /// Which part of the source image these settings ask for.
+    fn framing(&self) -> Result<cem::thumbnails::Framing, Box<dyn Error>> {
+        use cem::thumbnails::Framing;
+
+        match (self.crop, self.focus) {
+            (Some(_), Some(_)) => {
+                Err("A thumbnail can have a crop or a focus, not both".into())
+            }
+            (Some(crop), None) => Ok(Framing::Crop(crop)),
+            (None, Some(focus)) => Ok(Framing::Focus(focus)),
+            (None, None) => Ok(Framing::Whole),
+        }
+    }
+
+    /// Check that these settings make sense for a post with the given files,
+    /// and the given existing version (if any).
+    fn check(
+        &self,
+        files: &[EditPostFile],
+        old_post: Option<&db::Post>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.framing()?;
+
+        let marked_files = files.iter().filter(|file| file.thumbnail).count();
+        if marked_files > 1 {
+            return Err("Only one file can be marked as the thumbnail".into());
+        }
+
+        let sources =
+            [marked_files > 0, self.local_path.is_some(), self.separate_image];
+        if sources.into_iter().filter(|&source| source).count() > 1 {
+            return Err("A thumbnail can come from a file, a local_path, or \
+                 a separate_image, but only one"
+                .into());
+        }
+
+        if self.separate_image
+            && old_post.is_none_or(|post| post.thumbnail_extension.is_none())
+        {
+            return Err(
+                "This post has no separate thumbnail image to keep".into()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Where a post's thumbnails are made from, for telling whether they need to
+/// be made again.
+#[derive(PartialEq)]
+enum ThumbnailSource {
+    /// One of the post's existing files, left as it was, by ID
+    File(i32),
+    /// The post's existing separate thumbnail image
+    SeparateImage,
+    /// A file or separate image that's being copied in
+    New,
+}
+
+/// Whether a post is visible on the site yet.
+#[derive(
+    Clone, Copy, Default, PartialEq, serde::Deserialize, serde::Serialize,
+)]
+#[serde(rename_all = "lowercase")]
+enum PostState {
+    /// Only visible at its preview URL
+    Draft,
+    /// Visible once its timestamp comes around
+    Scheduled,
+    #[default]
+    Published,
+}
+
+impl PostState {
+    /// Check that this state makes sense for a post with the given timestamp.
+    fn check(
+        self,
+        timestamp: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<(), Box<dyn Error>> {
+        let in_future =
+            timestamp.is_some_and(|time| time > chrono::Utc::now());
+
+        match self {
+            PostState::Scheduled if !in_future => {
+                Err("A scheduled post needs a timestamp in the future".into())
+            }
+            PostState::Published if in_future => {
+                Err("This post's timestamp is in the future; set state = \
+                 \"scheduled\" to publish it then"
+                    .into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl TryFrom<String> for PostState {
+    type Error = Box<dyn Error + Send + Sync>;
+
+    fn try_from(state: String) -> Result<Self, Self::Error> {
+        match state.as_str() {
+            "draft" => Ok(PostState::Draft),
+            "scheduled" => Ok(PostState::Scheduled),
+            "published" => Ok(PostState::Published),
+            _ => Err(format!("Unknown post state: {state}").into()),
+        }
+    }
+}
+
+/// How a post file is presented on the site.
+#[derive(Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum FileKind {
+    Image,
+    Audio,
+    Video,
+    Download,
+}
+
+impl FileKind {
+    /// The name of this kind as stored in the `post_files.kind` column.
+    fn as_str(self) -> &'static str {
+        match self {
+            FileKind::Image => "image",
+            FileKind::Audio => "audio",
+            FileKind::Video => "video",
+            FileKind::Download => "download",
+        }
+    }
+
+    /// Parse the value of a `post_files.kind` column.
+    fn from_db(kind: &str) -> Result<Self, Box<dyn Error>> {
+        match kind {
+            "image" => Ok(FileKind::Image),
+            "audio" => Ok(FileKind::Audio),
+            "video" => Ok(FileKind::Video),
+            "download" => Ok(FileKind::Download),
+            _ => Err(format!("Unknown file kind in database: {kind}").into()),
+        }
+    }
+
+    /// Guess how a file should be presented based on its media type.
+    fn guess(media_type: &str) -> Self {
+        if SUPPORTED_IMAGE_TYPES.contains(&media_type) {
+            FileKind::Image
+        } else if media_type.starts_with("audio/") {
+            FileKind::Audio
+        } else if media_type.starts_with("video/") {
+            FileKind::Video
+        } else {
+            FileKind::Download
+        }
+    }
+
+    /// Make sure a file with the given media type can be presented as this
+    /// kind.
+    fn check(self, media_type: &str) -> Result<(), Box<dyn Error>> {
+        let ok = match self {
+            FileKind::Image => SUPPORTED_IMAGE_TYPES.contains(&media_type),
+            FileKind::Audio => media_type.starts_with("audio/"),
+            FileKind::Video => media_type.starts_with("video/"),
+            FileKind::Download => true,
+        };
+
+        if ok {
+            Ok(())
+        } else {
+            Err(format!(
+                "A file of type {media_type} can't be used as {}",
+                self.as_str()
+            )
+            .into())
+        }
+    }
 }
 
 /// A post and list of post files, as edited in TOML form
 #[derive(serde::Deserialize, serde::Serialize)]
 struct EditPostWithFiles {
     post: EditPost,
+    #[serde(default)]
+    thumbnail: EditThumbnail,
     files: Vec<EditPostFile>,
 }
 
@@ -85,32 +438,259 @@ struct SavePost {
     title: String,
     has_proper_title: bool,
     slug: String,
-    timestamp: Option<chrono::NaiveDateTime>,
+    timestamp: Option<chrono::DateTime<chrono::Utc>>,
+    timestamp_offset: Option<i32>,
+    draft: bool,
+    unlisted: bool,
     description: String,
     directory_id: i32,
+    /// Left as None (and so left alone when updating) unless the thumbnails
+    /// were regenerated or removed; the size of the part of the image they
+    /// show, if any
+    thumbnail_source_width: Option<Option<i32>>,
+    thumbnail_source_height: Option<Option<i32>>,
+    /// Only set for new posts, which count as last changed when they went up;
+    /// edits leave it to the database
+    updated_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl SavePost {
+    /// Check whether saving this over an existing post would change it.
+    ///
+    /// Diesel's updated_at trigger can't tell for itself, since the generated
+    /// search_vector column makes every update look like a change.
+    fn changes(&self, post: &db::Post) -> bool {
+        let changes_option = |new: Option<Option<i32>>, old: Option<i32>| {
+            new.is_some_and(|new| new != old)
+        };
+
+        self.title != post.title
+            || self.has_proper_title != post.has_proper_title
+            || self.slug != post.slug
+            || self.timestamp.is_some_and(|time| time != post.timestamp)
+            || self.timestamp_offset.is_some()
+                && self.timestamp_offset != post.timestamp_offset
+            || self.draft != post.draft
+            || self.unlisted != post.unlisted
+            || self.description != post.description
+            || self.directory_id != post.directory_id
+            || changes_option(
+                self.thumbnail_source_width,
+                post.thumbnail_source_width,
+            )
+            || changes_option(
+                self.thumbnail_source_height,
+                post.thumbnail_source_height,
+            )
+    }
 }
 
-/// A post file, to be saved in an insert statement.
+/// A post's thumbnail settings, to be saved along with SavePost.
 ///
-/// Existing post files don't need to be updated; they're all cleared out and
-/// reinserted every time.
-#[derive(diesel::Insertable)]
-#[diesel(table_name = db::post_images)]
+/// Unlike SavePost's fields, these are cleared when set to None.
+#[derive(PartialEq, diesel::AsChangeset, diesel::Insertable)]
+#[diesel(table_name = db::posts, treat_none_as_null = true)]
+struct SaveThumbnail {
+    thumbnail_file_id: Option<i32>,
+    thumbnail_extension: Option<String>,
+    thumbnail_crop_x: Option<i32>,
+    thumbnail_crop_y: Option<i32>,
+    thumbnail_crop_width: Option<i32>,
+    thumbnail_crop_height: Option<i32>,
+    thumbnail_focus_x: Option<i32>,
+    thumbnail_focus_y: Option<i32>,
+}
+
+impl SaveThumbnail {
+    fn new(
+        file_id: Option<i32>,
+        extension: Option<String>,
+        framing: cem::thumbnails::Framing,
+    ) -> Result<Self, Box<dyn Error>> {
+        use cem::thumbnails::Framing;
+
+        let mut thumbnail = SaveThumbnail {
+            thumbnail_file_id: file_id,
+            thumbnail_extension: extension,
+            thumbnail_crop_x: None,
+            thumbnail_crop_y: None,
+            thumbnail_crop_width: None,
+            thumbnail_crop_height: None,
+            thumbnail_focus_x: None,
+            thumbnail_focus_y: None,
+        };
+
+        match framing {
+            Framing::Whole => {}
+            Framing::Crop(crop) => {
+                thumbnail.thumbnail_crop_x = Some(crop.x.try_into()?);
+                thumbnail.thumbnail_crop_y = Some(crop.y.try_into()?);
+                thumbnail.thumbnail_crop_width = Some(crop.width.try_into()?);
+                thumbnail.thumbnail_crop_height =
+                    Some(crop.height.try_into()?);
+            }
+            Framing::Focus(focus) => {
+                thumbnail.thumbnail_focus_x = Some(focus.x.try_into()?);
+                thumbnail.thumbnail_focus_y = Some(focus.y.try_into()?);
+            }
+        }
+
+        Ok(thumbnail)
+    }
+
+    /// The thumbnail settings an existing post has now.
+    fn from_post(post: &db::Post) -> Self {
+        SaveThumbnail {
+            thumbnail_file_id: post.thumbnail_file_id,
+            thumbnail_extension: post.thumbnail_extension.clone(),
+            thumbnail_crop_x: post.thumbnail_crop_x,
+            thumbnail_crop_y: post.thumbnail_crop_y,
+            thumbnail_crop_width: post.thumbnail_crop_width,
+            thumbnail_crop_height: post.thumbnail_crop_height,
+            thumbnail_focus_x: post.thumbnail_focus_x,
+            thumbnail_focus_y: post.thumbnail_focus_y,
+        }
+    }
+}
+
+/// A new or replaced post file, to be saved in an insert or update statement.
+#[derive(diesel::AsChangeset, diesel::Insertable)]
+#[diesel(table_name = db::post_files)]
 struct SavePostFile {
+    /// Only set for new files whose ID was needed before they were inserted;
+    /// never changed by updates
+    id: Option<i32>,
     post_id: i32,
     order: i32,
     alt_text: String,
+    media_type: String,
+    extension: String,
+    kind: String,
+    size: i64,
+    width: Option<i32>,
+    height: Option<i32>,
+    /// Only set for files in new posts, which count as last changed when the
+    /// post went up
+    updated_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// A variant of a post file, to be saved in an insert statement.
+#[derive(diesel::Insertable)]
+#[diesel(table_name = db::post_file_variants)]
+struct SavePostFileVariant {
+    post_file_id: i32,
+    media_type: String,
+    extension: String,
+    width: i32,
+    height: i32,
+}
+
+/// A directory, to be saved either in an update or insert statement
+#[derive(diesel::AsChangeset, diesel::Insertable)]
+#[diesel(table_name = db::directories)]
+#[diesel(treat_none_as_null = true)]
+struct SaveDirectory {
+    title: String,
+    has_proper_title: bool,
+    slug: String,
+    parent_directory_id: Option<i32>,
+}
+
+/// An old path that should redirect to wherever that post or directory lives
+/// now.  Exactly one of the IDs is set.
+#[derive(diesel::Insertable)]
+#[diesel(table_name = db::redirects)]
+struct SaveRedirect {
+    path: String,
+    post_id: Option<i32>,
+    directory_id: Option<i32>,
+}
+
+/// A tag, to be saved in an insert statement if it doesn't exist yet.
+#[derive(diesel::Insertable)]
+#[diesel(table_name = db::tags)]
+struct SaveTag {
+    name: String,
+    slug: String,
+}
+
+/// A link between a post and a tag, to be saved in an insert statement.
+#[derive(diesel::Insertable)]
+#[diesel(table_name = db::post_tags)]
+struct SavePostTag {
+    post_id: i32,
+    tag_id: i32,
+}
+
+/// A post's files, as staged by `stage_files`.
+struct StagedFiles {
+    /// Info on each file that was copied in, or None for files that were
+    /// left alone
+    file_infos: Vec<Option<FileInfo>>,
+    /// The extension of the post's separate thumbnail image, if it has one
+    thumbnail_extension: Option<String>,
+    /// The size of the part of the source image the thumbnails show, if they
+    /// were made again, or Some(None) if the post has no thumbnails any more
+    thumbnail_size: Option<Option<(i32, i32)>>,
+}
+
+/// What we know about a post file, partly detected from its contents.
+struct FileInfo {
+    kind: FileKind,
+    media_type: String,
+    extension: String,
+    size: i64,
+    width: Option<i32>,
+    height: Option<i32>,
+    variants: Vec<VariantInfo>,
+}
+
+/// What we know about a variant of a post file.
+struct VariantInfo {
+    media_type: String,
+    extension: String,
+    width: i32,
+    height: i32,
+}
+
+/// The media types that can be shown as images.
+const SUPPORTED_IMAGE_TYPES: [&str; 5] =
+    ["image/png", "image/jpeg", "image/webp", "image/gif", "image/avif"];
+
+/// The image types thumbnails can't be made from.  The image crate can only
+/// decode AVIF with the system dav1d library, so posts that would get their
+/// thumbnails from an AVIF have to be given another source.
+const NO_THUMBNAIL_IMAGE_TYPES: [&str; 1] = ["image/avif"];
+
+/// A post's timestamp as stored: the time itself, and the offset from UTC it
+/// was entered with, if known.
+#[derive(diesel::Queryable)]
+struct StoredTimestamp(chrono::DateTime<chrono::Utc>, Option<i32>);
+
+impl From<StoredTimestamp> for Option<chrono::DateTime<chrono::FixedOffset>> {
+    /// Put the time back in the offset it was entered with, or the display
+    /// time zone if that isn't known.
+    fn from(StoredTimestamp(time, offset): StoredTimestamp) -> Self {
+        let time = match offset.and_then(chrono::FixedOffset::east_opt) {
+            Some(offset) => time.with_timezone(&offset),
+            None => cem::local_time(&time).fixed_offset(),
+        };
+
+        Some(time)
+    }
 }
 
 /// Serialize a chrono datetime as a toml datetime.
 fn chrono_to_toml<S: serde::Serializer>(
-    timestamp: &Option<chrono::NaiveDateTime>,
+    timestamp: &Option<chrono::DateTime<chrono::FixedOffset>>,
     serializer: S,
 ) -> Result<S::Ok, S::Error> {
     match timestamp {
         Some(timestamp) => {
+            let local =
+                timestamp.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true);
             let toml_dt: toml::value::Datetime =
-                timestamp.to_string().parse().unwrap();
+                local.parse().map_err(serde::ser::Error::custom)?;
             serializer.serialize_some(&toml_dt)
         }
         None => serializer.serialize_none(),
@@ -118,24 +698,45 @@ fn chrono_to_toml<S: serde::Serializer>(
 }
 
 /// Deserialize a toml datetime into a chrono datetime.
+///
+/// Times without an offset are taken to be in the display time zone.
 fn toml_to_chrono<'de, D: serde::Deserializer<'de>>(
     deserializer: D,
-) -> Result<Option<chrono::NaiveDateTime>, D::Error> {
+) -> Result<Option<chrono::DateTime<chrono::FixedOffset>>, D::Error> {
+    use serde::de::Error as _;
+
     let toml_dt: Option<toml::value::Datetime> =
         serde::Deserialize::deserialize(deserializer)?;
+    let Some(toml_dt) = toml_dt else { return Ok(None) };
 
-    match toml_dt {
-        Some(timestamp) => {
-            let time: chrono::DateTime<chrono::Utc> =
-                timestamp.to_string().parse().unwrap();
-            Ok(Some(time.naive_utc()))
-        }
-        None => Ok(None),
+    if toml_dt.date.is_none() || toml_dt.time.is_none() {
+        return Err(D::Error::custom("Expected a date and time"));
     }
+
+    let time = if toml_dt.offset.is_some() {
+        chrono::DateTime::parse_from_rfc3339(&toml_dt.to_string())
+            .map_err(D::Error::custom)?
+    } else {
+        let naive: chrono::NaiveDateTime =
+            toml_dt.to_string().parse().map_err(D::Error::custom)?;
+        naive
+            .and_local_timezone(cem::display_timezone())
+            .single()
+            .ok_or_else(|| {
+                D::Error::custom(
+                    "That time is skipped or repeated by a daylight saving \
+                     change; add an offset",
+                )
+            })?
+            .fixed_offset()
+    };
+
+    Ok(Some(time))
 }
 
 /// Open the given string in a text editor, call the given save function on the
 /// edited result, and repeat if there's an error.
+#[allow(clippy::type_complexity)]
 fn open_in_editor<T>(
     mut input: String,
     context: &mut T,
@@ -153,26 +754,16 @@ fn open_in_editor<T>(
             Err(error) => {
                 let error = error.to_string();
 
-                // Ask "Continue editing?" until we get either y or n
                 eprintln!("{}", error);
 
-                let stdin = std::io::stdin();
-                let mut response = String::new();
-                loop {
-                    eprint!("Continue editing? (y/n): ");
-                    response.clear();
-                    stdin.read_line(&mut response)?;
-
-                    match response.trim() {
-                        "y" => break,
-                        "n" => return Err("Exiting at user request".into()),
-                        _ => {}
-                    }
+                if !confirm("Continue editing?")? {
+                    return Err("Exiting at user request".into());
                 }
 
                 // Append error to toml as comment before re-editing,
                 // overwriting any previous error (assuming ### ERROR ###
                 // will never legitimately appear in a string or anything)
+                #[allow(clippy::redundant_static_lifetimes)]
                 const ERROR_HEADER: &'static str = "### ERROR ###\n";
 
                 if let Some(index) = input.find(ERROR_HEADER) {
@@ -188,266 +779,2245 @@ fn open_in_editor<T>(
     }
 }
 
-/// Create thumbnails in various sizes from the given source image.
-fn create_thumbnails(
-    thumbnails_dir: &PathBuf,
-    image_path: &PathBuf,
-) -> Result<(), Box<dyn Error>> {
-    for height in [100, 200, 300, 400, 1080] {
-        let source = image_path.display().to_string();
-        let dest =
-            thumbnails_dir.join(format!("{height}.png")).display().to_string();
-
-        #[rustfmt::skip]
-        let result = std::process::Command::new("convert")
-            .args([
-                &source,
-                // Some colours will be off by one after the gamma roundtrip.
-                // 0.456 is slightly higher than 1/2.2 but seems to result in
-                // the least error I can get out of ImageMagick's rounding.
-                "-gamma", "0.456",
-                "-filter", "box",
-                "-scale", &format!("x{height}"),
-                "-gamma", "2.2",
-                &dest
-            ])
-            .output()?;
+/// Describe an error in saving some TOML input as a JSON object, e.g.
+/// `{"error":"...","line":3,"column":1}`.  Only errors in the TOML itself
+/// have a line and column.
+fn error_json(
+    input: &str,
+    error: &(dyn Error + 'static),
+) -> serde_json::Value {
+    let Some(toml_error) = error.downcast_ref::<toml::de::Error>() else {
+        return serde_json::json!({ "error": error.to_string() });
+    };
 
-        if !result.status.success() {
-            return Err(String::from_utf8_lossy(&result.stderr).into());
-        }
+    let mut json = serde_json::json!({ "error": toml_error.message() });
 
-        let result = std::process::Command::new("optipng")
-            .args(["-strip", "all", &dest])
-            .output()?;
+    if let Some(span) = toml_error.span() {
+        let before = &input[..span.start];
+        let line_start = before.rfind('\n').map_or(0, |index| index + 1);
 
-        if !result.status.success() {
-            return Err(String::from_utf8_lossy(&result.stderr).into());
-        }
+        json["line"] = (before.matches('\n').count() + 1).into();
+        json["column"] = (before[line_start..].chars().count() + 1).into();
     }
 
-    Ok(())
+    json
 }
 
-/// Copy post files into the right places and generate thumbnails; return the
-/// filesystem path of the directory containing these files.
-fn handle_files(
-    files: &[EditPostFile],
-    context: &mut PostContext,
-) -> Result<PathBuf, Box<dyn Error>> {
-    // Create directories
-    let id_string = match context.post_id {
-        Some(id) => id.to_string(),
-        None => "tmp".to_string(),
-    };
-
-    let post_dir = context.config.upload_dir.join(id_string);
-    let files_dir = post_dir.join("files");
-    let thumbnails_dir = post_dir.join("thumbnails");
-
-    std::fs::create_dir_all(&files_dir)?;
-    std::fs::create_dir_all(&thumbnails_dir)?;
-
-    // Process files
-    for (i, file) in (1..).zip(files.iter()) {
-        if let Some(path) = file.local_path.as_ref() {
-            let filename = files_dir.join(format!("{i}.png"));
-            std::fs::copy(&path, &filename)?;
+/// An error that has already been reported, so `main` only needs to exit with
+/// a failure status.
+#[derive(Debug)]
+struct AlreadyReported;
 
-            if i == 1 {
-                create_thumbnails(&thumbnails_dir, &filename)?;
-            }
-        }
+impl std::fmt::Display for AlreadyReported {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Error already reported")
     }
+}
+
+impl Error for AlreadyReported {}
 
-    Ok(post_dir)
+/// Print an error to stderr as a line of JSON (see `error_json`), and return
+/// an error saying so.
+fn report_json(input: &str, error: Box<dyn Error>) -> Box<dyn Error> {
+    eprintln!("{}", error_json(input, error.as_ref()));
+    AlreadyReported.into()
 }
 
-/// Resolve a post or directory's URL path to the parent directory ID and slug.
-fn find_parent_id(
-    path: &str,
-    connection: &mut diesel::PgConnection,
-) -> Result<(i32, String), Box<dyn Error>> {
-    let mut split_path = path.rsplitn(2, '/');
-    let Some(slug) = split_path.next() else {
-        return Err("Invalid post path".into());
-    };
-    let Some(dir_path) = split_path.next() else {
-        return Err("Invalid post path".into());
+/// Read TOML input from a file, or from stdin if the path is "-", and save it
+/// without any prompting.
+///
+/// Errors are printed to stderr as a line of JSON (see `error_json`), and the
+/// CLI exits with status 1, so scripts can tell what went wrong.
+#[allow(clippy::type_complexity)]
+fn save_from<T>(
+    from: &Path,
+    context: &mut T,
+    save: fn(&str, &mut T) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let input = if from == Path::new("-") {
+        std::io::read_to_string(std::io::stdin())
+    } else {
+        std::fs::read_to_string(from)
     };
 
-    let dir_id = db::directory_paths::table
-        .filter(db::directory_paths::path.eq(dir_path))
-        .select(db::directory_paths::directory_id)
-        .first(connection)
-        .optional()?;
-
-    let Some(dir_id) = dir_id else {
-        return Err(format!("Directory not found: {dir_path}").into());
+    let result = match &input {
+        Ok(input) => save(input, context),
+        Err(error) => Err(format!("{}: {error}", from.display()).into()),
     };
 
-    Ok((dir_id, slug.to_string()))
+    result.map_err(|error| {
+        report_json(input.as_deref().unwrap_or_default(), error)
+    })
 }
 
-/// Save a post to the database.
-fn save_post_db(
-    connection: &mut diesel::PgConnection,
-    bundle: EditPostWithFiles,
-    post_id: Option<i32>,
-    directory_id: i32,
-    slug: String,
-) -> Result<i32, Box<dyn Error>> {
-    // Save post
-    let new_post = SavePost {
-        title: bundle.post.title,
-        has_proper_title: bundle.post.has_proper_title,
-        slug: slug,
-        timestamp: bundle.post.timestamp,
-        description: bundle.post.description,
-        directory_id: directory_id,
-    };
-
-    let id = match post_id {
-        // Update post
-        Some(id) => {
-            diesel::update(db::posts::table)
-                .filter(db::posts::id.eq(id))
-                .set(&new_post)
-                .execute(connection)?;
+/// The directory relative local_paths in TOML input from `save_from` are
+/// relative to.
+fn from_base_dir(from: &Path) -> PathBuf {
+    match from.parent() {
+        Some(parent) if from != Path::new("-") => parent.to_path_buf(),
+        _ => PathBuf::new(),
+    }
+}
 
-            // Delete file rows so we can reinsert them
-            diesel::delete(db::post_images::table)
-                .filter(db::post_images::post_id.eq(id))
-                .execute(connection)?;
+/// Detect the format of a post file from its contents, and check that it can
+/// be presented as the requested kind (if any).
+fn detect_file_info(
+    path: &Path,
+    kind: Option<FileKind>,
+) -> Result<FileInfo, Box<dyn Error>> {
+    let size = std::fs::metadata(path)?.len().try_into()?;
+    let local_extension = path.extension().and_then(|ext| ext.to_str());
 
-            id
+    let (media_type, extension) = match infer::get_from_path(path)? {
+        Some(detected) => {
+            // Downloads keep their original extension, since e.g. a lot of
+            // formats are secretly just zip files
+            let extension = match (kind, local_extension) {
+                (Some(FileKind::Download), Some(ext)) => ext,
+                _ => detected.extension(),
+            };
+            (detected.mime_type(), extension)
         }
-
-        // Insert new post
-        None => diesel::insert_into(db::posts::table)
-            .values(&new_post)
-            .returning(db::posts::id)
-            .get_result(connection)?,
+        None => match kind {
+            Some(FileKind::Download) | None => {
+                ("application/octet-stream", local_extension.unwrap_or("bin"))
+            }
+            Some(kind) => {
+                return Err(format!(
+                    "Unknown file type can't be used as {}: {}",
+                    kind.as_str(),
+                    path.display()
+                )
+                .into())
+            }
+        },
     };
 
-    diesel::sql_query("refresh materialized view post_paths;")
-        .execute(connection)?;
-
-    // Save files
-    let new_files: Vec<SavePostFile> = (1..)
-        .zip(bundle.files.into_iter())
-        .map(|(i, file)| SavePostFile {
-            post_id: id,
-            order: i,
-            alt_text: file.alt_text,
-        })
-        .collect();
+    let kind = kind.unwrap_or_else(|| FileKind::guess(media_type));
+    kind.check(media_type)?;
 
-    diesel::insert_into(db::post_images::table)
-        .values(new_files)
-        .execute(connection)?;
+    let (width, height) = match kind {
+        FileKind::Image => {
+            let dimensions = imagesize::size(path)?;
+            (
+                Some(dimensions.width.try_into()?),
+                Some(dimensions.height.try_into()?),
+            )
+        }
+        _ => (None, None),
+    };
 
-    Ok(id)
+    Ok(FileInfo {
+        kind: kind,
+        media_type: media_type.to_string(),
+        extension: extension.to_lowercase(),
+        size: size,
+        width: width,
+        height: height,
+        variants: vec![],
+    })
 }
 
-/// Save a post, either edited or new, and add any files and thumbnails.
-fn save_post(
-    input: &str,
-    context: &mut PostContext,
-) -> Result<(), Box<dyn Error>> {
-    // These steps are ordered based on what should error out first
-
-    // Parse edited toml form
-    let bundle: EditPostWithFiles = toml::from_str(input)?;
+/// Where posts' files are staged while they're being saved, relative to the
+/// upload directory.
+const STAGING_DIR: &str = "tmp";
 
-    // Confirm directory ID before making any changes
-    let (directory_id, slug) =
-        find_parent_id(&bundle.post.path, context.connection)?;
+/// Recreate a directory tree at a new path, hard-linking files where possible
+/// and copying them otherwise.
+///
+/// Files staged this way share their contents with the originals, so they
+/// must be removed and recreated rather than written to.
+fn link_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
 
-    // Move files around *before* db stuff so we can avoid eating a post ID if
-    // there's an error here
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
 
-    // XXX: When editing a post, we might mess with the existing files and then
-    // end up bailing.  Doing this last wouldn't fully avoid that, either.  I
-    // can fix it manually if it happens I guess.
-    let files_dir = handle_files(&bundle.files, context)?;
+        if entry.file_type()?.is_dir() {
+            link_dir(&entry.path(), &target)?;
+        } else {
+            link_file(&entry.path(), &target)?;
+        }
+    }
 
-    // Save everything to db
-    let new_id = context.connection.transaction(|connection| {
-        save_post_db(connection, bundle, context.post_id, directory_id, slug)
-    })?;
+    Ok(())
+}
 
-    // Rename file directory if we didn't have the post ID before
-    if context.post_id.is_none() {
-        std::fs::rename(
-            &files_dir,
-            &files_dir.with_file_name(new_id.to_string()),
-        )?;
+/// Hard-link a file to a new path, or copy it if that doesn't work.
+fn link_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if std::fs::hard_link(from, to).is_err() {
+        std::fs::copy(from, to)?;
     }
 
     Ok(())
 }
 
-/// Create a new post.
-fn new_post(
-    connection: &mut diesel::PgConnection,
-    config: cem::CEMConfig,
+/// Check that every file in an edited post is either new or one of the post's
+/// existing files, listed once.
+fn check_file_ids(
+    files: &[EditPostFile],
+    old_files: &[db::PostFile],
 ) -> Result<(), Box<dyn Error>> {
-    let empty_post = EditPostWithFiles {
-        post: Default::default(),
-        files: vec![EditPostFile {
-            local_path: Some("".into()),
-            ..Default::default()
-        }],
+    let mut seen = std::collections::HashSet::new();
+
+    for (i, file) in (1..).zip(files) {
+        match file.id {
+            Some(id) if !old_files.iter().any(|old| old.id == id) => {
+                return Err(format!("File {i} has an unknown id: {id}").into());
+            }
+            Some(id) if !seen.insert(id) => {
+                return Err(
+                    format!("File {i} is listed twice (id {id})").into()
+                );
+            }
+            None if file.local_path.is_none() => {
+                return Err(format!("File {i} needs a local_path").into());
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Find the file a post's thumbnails should be made from: the one marked as
+/// the thumbnail, or else the first image.  Return its position in the list,
+/// and its ID if it's an existing file that isn't being replaced.
+///
+/// It's an error for that to be an image thumbnails can't be made from, even
+/// if a later one would do, so the post's thumbnail is always chosen.
+#[allow(clippy::type_complexity)]
+fn find_thumbnail_file(
+    files: &[EditPostFile],
+    file_infos: &[Option<FileInfo>],
+    old_files: &[db::PostFile],
+) -> Result<Option<(usize, Option<i32>)>, Box<dyn Error>> {
+    let is_image = |index: usize| {
+        let file = &files[index];
+        match (&file_infos[index], file.id) {
+            (Some(file_info), _) => file_info.kind == FileKind::Image,
+            (None, Some(id)) => match file.kind {
+                Some(kind) => kind == FileKind::Image,
+                None => old_files
+                    .iter()
+                    .any(|old| old.id == id && old.kind == "image"),
+            },
+            (None, None) => false,
+        }
+    };
+
+    let index = match files.iter().position(|file| file.thumbnail) {
+        Some(index) => {
+            if !is_image(index) {
+                return Err(format!(
+                    "File {} isn't an image, so thumbnails can't be made from \
+                     it",
+                    index + 1
+                )
+                .into());
+            }
+            index
+        }
+        None => match (0..files.len()).find(|&index| is_image(index)) {
+            Some(index) => index,
+            None => return Ok(None),
+        },
+    };
+
+    let media_type = match (&file_infos[index], files[index].id) {
+        (Some(file_info), _) => Some(file_info.media_type.as_str()),
+        (None, Some(id)) => old_files
+            .iter()
+            .find(|old| old.id == id)
+            .map(|old| old.media_type.as_str()),
+        (None, None) => None,
+    };
+    if let Some(media_type) = media_type
+        .filter(|media_type| NO_THUMBNAIL_IMAGE_TYPES.contains(media_type))
+    {
+        return Err(format!(
+            "Thumbnails can't be made from file {} ({media_type}); mark \
+             another image with thumbnail = true, or give [thumbnail] a \
+             local_path",
+            index + 1
+        )
+        .into());
+    }
+
+    let unchanged_id = match file_infos[index] {
+        Some(_) => None,
+        None => files[index].id,
+    };
+
+    Ok(Some((index, unchanged_id)))
+}
+
+/// Check that a thumbnail framing fits within a source image of the given
+/// size.
+fn check_framing(
+    framing: cem::thumbnails::Framing,
+    width: u32,
+    height: u32,
+) -> Result<(), Box<dyn Error>> {
+    use cem::thumbnails::Framing;
+
+    match framing {
+        Framing::Crop(crop) if crop.width == 0 || crop.height == 0 => {
+            Err("The thumbnail crop is empty".into())
+        }
+        Framing::Crop(crop)
+            if crop.x.saturating_add(crop.width) > width
+                || crop.y.saturating_add(crop.height) > height =>
+        {
+            Err(format!(
+                "The thumbnail crop doesn't fit in the {width}x{height} \
+                 source image"
+            )
+            .into())
+        }
+        Framing::Focus(focus) if focus.x >= width || focus.y >= height => {
+            Err(format!(
+                "The thumbnail focus is outside the {width}x{height} source \
+                 image"
+            )
+            .into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Stage a post's files in a new temporary directory, numbered in their new
+/// order: existing files are linked in from the post's directory, and new
+/// files copied in, along with thumbnails and variants.  Return the staging
+/// directory, and what was staged.
+///
+/// Nothing outside the staging directory is touched, so the post's files stay
+/// as they were until `swap_in_files`, and dropping the staging directory on
+/// an error rolls everything back.
+fn stage_files(
+    bundle: &EditPostWithFiles,
+    old_post: Option<&db::Post>,
+    old_files: &[db::PostFile],
+    context: &mut PostContext,
+) -> Result<(tempfile::TempDir, StagedFiles), Box<dyn Error>> {
+    let files = &bundle.files;
+    let thumbnail = &bundle.thumbnail;
+
+    // Check every file before touching anything
+    check_file_ids(files, old_files)?;
+    thumbnail.check(files, old_post)?;
+
+    let mut file_infos = files
+        .iter()
+        .map(|file| {
+            file.local_path
+                .as_deref()
+                .map(|path| detect_file_info(path, file.kind))
+                .transpose()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let thumbnail_info = thumbnail
+        .local_path
+        .as_deref()
+        .map(|path| {
+            let info = detect_file_info(path, Some(FileKind::Image))?;
+            if NO_THUMBNAIL_IMAGE_TYPES.contains(&info.media_type.as_str()) {
+                return Err(format!(
+                    "Thumbnails can't be made from {}: {}",
+                    info.media_type,
+                    path.display()
+                )
+                .into());
+            }
+            Ok::<_, Box<dyn Error>>(info)
+        })
+        .transpose()?;
+
+    // Create directories.  The prefix is just so it's clear which post a
+    // directory belongs to; `clean_tmp` goes by `staged_saves`.
+    let staging_root = context.config.upload_dir.join(STAGING_DIR);
+    std::fs::create_dir_all(&staging_root)?;
+
+    let prefix = match context.post_id {
+        Some(id) => format!("{id}-"),
+        None => "new-".to_string(),
+    };
+    let staging =
+        tempfile::Builder::new().prefix(&prefix).tempdir_in(&staging_root)?;
+    // Temporary directories are private by default, but this one is going to
+    // be served from
+    std::fs::set_permissions(
+        staging.path(),
+        std::fs::metadata(&staging_root)?.permissions(),
+    )?;
+    std::fs::File::create(staging.path().join(STAGED_MARKER))?;
+
+    let files_dir = staging.path().join("files");
+    let thumbnails_dir = staging.path().join("thumbnails");
+    let variants_dir = staging.path().join("variants");
+
+    std::fs::create_dir_all(&files_dir)?;
+    std::fs::create_dir_all(&thumbnails_dir)?;
+    std::fs::create_dir_all(&variants_dir)?;
+
+    let old_dir = context
+        .post_id
+        .map(|id| context.config.upload_dir.join(id.to_string()))
+        .unwrap_or_default();
+
+    // Process files
+    for (i, (file, file_info)) in (1..).zip(files.iter().zip(&mut file_infos))
+    {
+        match (&file.local_path, file_info) {
+            // New or replaced file
+            (Some(path), Some(file_info)) => {
+                let filename =
+                    files_dir.join(format!("{i}.{}", file_info.extension));
+                std::fs::copy(path, &filename)?;
+
+                if file_info.kind != FileKind::Image {
+                    continue;
+                }
+
+                let variants = cem::variants::create_variants(
+                    &variants_dir,
+                    &filename,
+                    &file_info.media_type,
+                    i,
+                )?;
+
+                for variant in variants {
+                    file_info.variants.push(VariantInfo {
+                        media_type: variant.format.media_type().to_string(),
+                        extension: variant.format.extension().to_string(),
+                        width: variant.width.try_into()?,
+                        height: variant.height.try_into()?,
+                    });
+                }
+            }
+
+            // Existing file, which may have moved
+            _ => {
+                let Some(old) =
+                    old_files.iter().find(|old| Some(old.id) == file.id)
+                else {
+                    continue;
+                };
+
+                link_file(
+                    &old_dir
+                        .join("files")
+                        .join(format!("{}.{}", old.order, old.extension)),
+                    &files_dir.join(format!("{i}.{}", old.extension)),
+                )?;
+
+                let old_prefix = format!("{}-", old.order);
+                let old_variants_dir = old_dir.join("variants");
+                if !old_variants_dir.exists() {
+                    continue;
+                }
+                for entry in std::fs::read_dir(old_variants_dir)? {
+                    let entry = entry?;
+                    let name = entry.file_name();
+                    let Some(rest) = name
+                        .to_str()
+                        .and_then(|name| name.strip_prefix(&old_prefix))
+                    else {
+                        continue;
+                    };
+
+                    link_file(
+                        &entry.path(),
+                        &variants_dir.join(format!("{i}-{rest}")),
+                    )?;
+                }
+            }
+        }
+    }
+
+    // Find the thumbnail source, bringing along any separate image
+    let (source, source_path) = if let (Some(path), Some(thumbnail_info)) =
+        (&thumbnail.local_path, &thumbnail_info)
+    {
+        let source_path = staging
+            .path()
+            .join(format!("thumbnail.{}", thumbnail_info.extension));
+        std::fs::copy(path, &source_path)?;
+        (Some(ThumbnailSource::New), Some(source_path))
+    } else if let (true, Some(extension)) = (
+        thumbnail.separate_image,
+        old_post.and_then(|post| post.thumbnail_extension.as_ref()),
+    ) {
+        let source_path =
+            staging.path().join(format!("thumbnail.{extension}"));
+        link_file(
+            &old_dir.join(format!("thumbnail.{extension}")),
+            &source_path,
+        )?;
+        (Some(ThumbnailSource::SeparateImage), Some(source_path))
+    } else if let Some((index, unchanged_id)) =
+        find_thumbnail_file(files, &file_infos, old_files)?
+    {
+        let extension = match &file_infos[index] {
+            Some(file_info) => &file_info.extension,
+            None => {
+                &old_files
+                    .iter()
+                    .find(|old| Some(old.id) == files[index].id)
+                    .ok_or("Expected thumbnail source")?
+                    .extension
+            }
+        };
+
+        (
+            Some(match unchanged_id {
+                Some(id) => ThumbnailSource::File(id),
+                None => ThumbnailSource::New,
+            }),
+            Some(files_dir.join(format!("{}.{extension}", index + 1))),
+        )
+    } else {
+        (None, None)
+    };
+
+    // Thumbnails only need to be made again if they'd come from a different
+    // image, or a different part of it.  Keeping the old ones keeps any made
+    // on request at other sizes.
+    let old_source = old_post.and_then(|post| {
+        if post.thumbnail_extension.is_some() {
+            return Some(ThumbnailSource::SeparateImage);
+        }
+
+        old_files
+            .iter()
+            .filter(|old| match post.thumbnail_file_id {
+                Some(id) => old.id == id,
+                None => old.kind == "image",
+            })
+            .min_by_key(|old| old.order)
+            .map(|old| ThumbnailSource::File(old.id))
+    });
+    let old_framing =
+        old_post.map(db::Post::thumbnail_framing).unwrap_or_default();
+    let framing = thumbnail.framing()?;
+    let old_thumbnails_dir = old_dir.join("thumbnails");
+
+    let thumbnail_size = match source_path {
+        Some(_)
+            if source == old_source
+                && framing == old_framing
+                && old_thumbnails_dir.exists() =>
+        {
+            link_dir(&old_thumbnails_dir, &thumbnails_dir)?;
+            None
+        }
+        Some(source_path) => {
+            let dimensions = imagesize::size(&source_path)?;
+            let width: u32 = dimensions.width.try_into()?;
+            let height: u32 = dimensions.height.try_into()?;
+            check_framing(framing, width, height)?;
+
+            cem::thumbnails::create_thumbnails(
+                &thumbnails_dir,
+                &source_path,
+                framing,
+            )?;
+
+            let region = framing.region(width, height);
+            Some(Some((region.width.try_into()?, region.height.try_into()?)))
+        }
+        None => Some(None),
+    };
+
+    let staged = StagedFiles {
+        file_infos: file_infos,
+        thumbnail_extension: match (thumbnail_info, thumbnail.separate_image) {
+            (Some(thumbnail_info), _) => Some(thumbnail_info.extension),
+            (None, true) => {
+                old_post.and_then(|post| post.thumbnail_extension.clone())
+            }
+            (None, false) => None,
+        },
+        thumbnail_size: thumbnail_size,
+    };
+
+    Ok((staging, staged))
+}
+
+/// Put a post's staged files in place, once the post has been saved.
+fn swap_in_files(
+    staging: tempfile::TempDir,
+    post_dir: &Path,
+) -> std::io::Result<()> {
+    swap_in_dir(&staging.into_path(), post_dir)
+}
+
+/// Swap a staged directory in for a post's directory, and remove the old one.
+///
+/// Staged directories hold `STAGED_MARKER` until they're in place, so if this
+/// is interrupted, `clean_tmp` can tell whether the directory at `staged`
+/// still has the new files or has the old ones now.
+fn swap_in_dir(staged: &Path, post_dir: &Path) -> std::io::Result<()> {
+    if post_dir.exists() {
+        exchange_dirs(staged, post_dir)?;
+    } else {
+        std::fs::rename(staged, post_dir)?;
+    }
+
+    std::fs::remove_file(post_dir.join(STAGED_MARKER))?;
+    if staged.exists() {
+        std::fs::remove_dir_all(staged)?;
+    }
+
+    Ok(())
+}
+
+/// Swap two directories in one step, so the post whose directory one of them
+/// is always has its files.
+#[cfg(target_os = "linux")]
+fn exchange_dirs(a: &Path, b: &Path) -> std::io::Result<()> {
+    use rustix::fs::{renameat_with, RenameFlags, CWD};
+    use rustix::io::Errno;
+
+    match renameat_with(CWD, a, CWD, b, RenameFlags::EXCHANGE) {
+        // Not every filesystem can do it
+        Err(Errno::INVAL | Errno::NOSYS) => rename_dirs(a, b),
+        result => Ok(result?),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn exchange_dirs(a: &Path, b: &Path) -> std::io::Result<()> {
+    rename_dirs(a, b)
+}
+
+/// Swap two directories by moving one aside, for where `exchange_dirs` can't
+/// do it in one step.  In between, there's nothing at `b`.
+fn rename_dirs(a: &Path, b: &Path) -> std::io::Result<()> {
+    let aside = a.with_extension("old");
+    std::fs::rename(b, &aside)?;
+    std::fs::rename(a, b)?;
+    std::fs::rename(&aside, a)
+}
+
+/// A file in each staging directory that marks it as holding a save's new
+/// files, rather than the old ones they've been swapped with.
+const STAGED_MARKER: &str = ".staged";
+
+/// Clean up the staging directory after saves that didn't finish.
+///
+/// Saves listed in `staged_saves` were committed, so their staged files are
+/// swapped in, however far that got.  Anything else was never saved, and is
+/// removed.
+fn clean_tmp(
+    connection: &mut diesel::PgConnection,
+    config: &cem::CEMConfig,
+) -> Result<(), Box<dyn Error>> {
+    let saves: std::collections::HashMap<String, i32> =
+        db::staged_saves::table
+            .select((db::staged_saves::staging_dir, db::staged_saves::post_id))
+            .load::<(String, i32)>(connection)?
+            .into_iter()
+            .collect();
+
+    let staging_root = config.upload_dir.join(STAGING_DIR);
+    let mut names = std::collections::BTreeSet::new();
+    if staging_root.exists() {
+        for entry in std::fs::read_dir(&staging_root)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            let name = match name.strip_suffix(".old") {
+                Some(staged) => staged.to_string(),
+                None => name,
+            };
+            names.insert(name);
+        }
+    }
+
+    let remove = |path: &Path| -> std::io::Result<()> {
+        if path.is_dir() {
+            std::fs::remove_dir_all(path)?;
+        } else if path.exists() {
+            std::fs::remove_file(path)?;
+        } else {
+            return Ok(());
+        }
+        println!("Removed {}", path.display());
+        Ok(())
+    };
+
+    for name in &names {
+        let staged = staging_root.join(name);
+        // Left by rename_dirs, holding the post's old files
+        let old = staging_root.join(format!("{name}.old"));
+
+        if let Some(&post_id) = saves.get(name) {
+            let post_dir = config.upload_dir.join(post_id.to_string());
+            if staged.join(STAGED_MARKER).exists() {
+                swap_in_dir(&staged, &post_dir)?;
+                println!("Finished saving files for post {post_id}");
+            } else if post_dir.join(STAGED_MARKER).exists() {
+                std::fs::remove_file(post_dir.join(STAGED_MARKER))?;
+            }
+        }
+
+        // Whatever's left is old files, or never got saved
+        remove(&staged)?;
+        remove(&old)?;
+    }
+
+    // Every save that was left is dealt with now, or had finished already
+    diesel::delete(db::staged_saves::table).execute(connection)?;
+
+    Ok(())
+}
+
+/// Turn a tag name into a URL slug, e.g. "Cat's Eye" -> "cat-s-eye".
+fn slugify(name: &str) -> Result<String, Box<dyn Error>> {
+    let slug = name
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-");
+
+    if slug.is_empty() {
+        return Err(format!("Invalid tag name: {name:?}").into());
+    }
+
+    Ok(slug)
+}
+
+/// Replace a post's tags with the given list, creating any new tags.
+fn save_tags(
+    connection: &mut diesel::PgConnection,
+    post_id: i32,
+    names: Vec<String>,
+) -> Result<(), Box<dyn Error>> {
+    let new_tags = names
+        .into_iter()
+        .map(|name| {
+            let name = name.trim().to_string();
+            Ok(SaveTag { slug: slugify(&name)?, name: name })
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    let slugs: Vec<&str> =
+        new_tags.iter().map(|tag| tag.slug.as_str()).collect();
+
+    // Existing tags keep their existing names
+    diesel::insert_into(db::tags::table)
+        .values(&new_tags)
+        .on_conflict(db::tags::slug)
+        .do_nothing()
+        .execute(connection)?;
+
+    let tag_ids: Vec<i32> = db::tags::table
+        .filter(db::tags::slug.eq_any(slugs))
+        .select(db::tags::id)
+        .load(connection)?;
+
+    diesel::delete(db::post_tags::table)
+        .filter(db::post_tags::post_id.eq(post_id))
+        .execute(connection)?;
+
+    let new_post_tags: Vec<SavePostTag> = tag_ids
+        .into_iter()
+        .map(|tag_id| SavePostTag { post_id: post_id, tag_id: tag_id })
+        .collect();
+
+    diesel::insert_into(db::post_tags::table)
+        .values(new_post_tags)
+        .execute(connection)?;
+
+    Ok(())
+}
+
+/// Resolve a post or directory's URL path to the parent directory ID and slug.
+fn find_parent_id(
+    path: &str,
+    connection: &mut diesel::PgConnection,
+) -> Result<(i32, String), Box<dyn Error>> {
+    let mut split_path = path.rsplitn(2, '/');
+    let Some(slug) = split_path.next() else {
+        return Err("Invalid post path".into());
+    };
+    let Some(dir_path) = split_path.next() else {
+        return Err("Invalid post path".into());
+    };
+
+    let dir_id = db::directory_paths::table
+        .filter(db::directory_paths::path.eq(dir_path))
+        .select(db::directory_paths::directory_id)
+        .first(connection)
+        .optional()?;
+
+    let Some(dir_id) = dir_id else {
+        return Err(format!("Directory not found: {dir_path}").into());
+    };
+
+    Ok((dir_id, slug.to_string()))
+}
+
+/// Resolve a directory's URL path to its parent directory ID (None for
+/// top-level directories) and slug.
+fn find_directory_parent_id(
+    path: &str,
+    connection: &mut diesel::PgConnection,
+) -> Result<(Option<i32>, String), Box<dyn Error>> {
+    match path.rsplit_once('/') {
+        Some(("", slug)) if !slug.is_empty() => Ok((None, slug.to_string())),
+        _ => {
+            let (parent_id, slug) = find_parent_id(path, connection)?;
+            Ok((Some(parent_id), slug))
+        }
+    }
+}
+
+/// Find a directory's ID from its URL path.
+fn find_directory_id(
+    path: &str,
+    connection: &mut diesel::PgConnection,
+) -> Result<i32, Box<dyn Error>> {
+    let id = db::directory_paths::table
+        .filter(db::directory_paths::path.eq(path))
+        .select(db::directory_paths::directory_id)
+        .first(connection)
+        .optional()?;
+
+    id.ok_or_else(|| format!("Directory not found: {path}").into())
+}
+
+/// Refresh both the directory and post path views after anything that might
+/// change a path, and redirect the old path of every post or directory that
+/// moved.
+///
+/// Moving a directory moves everything inside it, so this compares the whole
+/// views before and after rather than trying to work out what was affected.
+fn refresh_paths(
+    connection: &mut diesel::PgConnection,
+) -> Result<(), Box<dyn Error>> {
+    use std::collections::HashMap;
+
+    let old_directory_paths: HashMap<i32, String> = db::directory_paths::table
+        .select((db::directory_paths::directory_id, db::directory_paths::path))
+        .load(connection)?
+        .into_iter()
+        .collect();
+    let old_post_paths: HashMap<i32, String> = db::post_paths::table
+        .select((db::post_paths::post_id, db::post_paths::path))
+        .load(connection)?
+        .into_iter()
+        .collect();
+
+    // post_paths is built from directory_paths, so this order matters
+    diesel::sql_query("refresh materialized view directory_paths;")
+        .execute(connection)?;
+    diesel::sql_query("refresh materialized view post_paths;")
+        .execute(connection)?;
+
+    let new_directory_paths: HashMap<i32, String> = db::directory_paths::table
+        .select((db::directory_paths::directory_id, db::directory_paths::path))
+        .load(connection)?
+        .into_iter()
+        .collect();
+    let new_post_paths: HashMap<i32, String> = db::post_paths::table
+        .select((db::post_paths::post_id, db::post_paths::path))
+        .load(connection)?
+        .into_iter()
+        .collect();
+
+    // Anything that was deleted just goes away, rather than being redirected
+    let moved = |old: HashMap<i32, String>, new: &HashMap<i32, String>| {
+        old.into_iter()
+            .filter(|(id, path)| new.get(id).is_some_and(|new| new != path))
+            .collect::<Vec<_>>()
+    };
+
+    let mut redirects: Vec<SaveRedirect> =
+        moved(old_directory_paths, &new_directory_paths)
+            .into_iter()
+            .map(|(id, path)| SaveRedirect {
+                path: path,
+                post_id: None,
+                directory_id: Some(id),
+            })
+            .collect();
+    redirects.extend(moved(old_post_paths, &new_post_paths).into_iter().map(
+        |(id, path)| SaveRedirect {
+            path: path,
+            post_id: Some(id),
+            directory_id: None,
+        },
+    ));
+
+    // Paths that are in use again shouldn't redirect anywhere
+    diesel::delete(db::redirects::table)
+        .filter(
+            db::redirects::path
+                .eq_any(new_directory_paths.values())
+                .or(db::redirects::path.eq_any(new_post_paths.values())),
+        )
+        .execute(connection)?;
+
+    // Postgres won't upsert the same row twice in one statement, so these
+    // have to go one at a time
+    for redirect in redirects {
+        diesel::insert_into(db::redirects::table)
+            .values(&redirect)
+            .on_conflict(db::redirects::path)
+            .do_update()
+            .set((
+                db::redirects::post_id.eq(redirect.post_id),
+                db::redirects::directory_id.eq(redirect.directory_id),
+            ))
+            .execute(connection)?;
+    }
+
+    Ok(())
+}
+
+/// Ask a yes/no question on the terminal.
+fn confirm(prompt: &str) -> Result<bool, Box<dyn Error>> {
+    let stdin = std::io::stdin();
+    let mut response = String::new();
+
+    loop {
+        eprint!("{prompt} (y/n): ");
+        response.clear();
+        stdin.read_line(&mut response)?;
+
+        match response.trim() {
+            "y" => return Ok(true),
+            "n" => return Ok(false),
+            _ => {}
+        }
+    }
+}
+
+/// Save a post to the database.
+fn save_post_db(
+    connection: &mut diesel::PgConnection,
+    bundle: EditPostWithFiles,
+    staged: StagedFiles,
+    old_post: Option<db::Post>,
+    old_files: Vec<db::PostFile>,
+    directory_id: i32,
+    slug: String,
+) -> Result<i32, Box<dyn Error>> {
+    let post_id = old_post.as_ref().map(|post| post.id);
+
+    // A new file marked as the thumbnail needs its ID before it's inserted,
+    // to go on the post
+    let thumbnail_index = bundle.files.iter().position(|file| file.thumbnail);
+    let thumbnail_file_id = match thumbnail_index {
+        Some(index) => match bundle.files[index].id {
+            Some(id) => Some(id),
+            None => Some(
+                diesel::select(
+                    diesel::dsl::sql::<diesel::sql_types::Integer>(
+                        "nextval('post_files_id_seq')::integer",
+                    ),
+                )
+                .get_result(connection)?,
+            ),
+        },
+        None => None,
+    };
+    let thumbnail = SaveThumbnail::new(
+        thumbnail_file_id,
+        staged.thumbnail_extension,
+        bundle.thumbnail.framing()?,
+    )?;
+
+    // Save post
+    let new_post = SavePost {
+        title: bundle.post.title,
+        has_proper_title: bundle.post.has_proper_title,
+        slug: slug,
+        timestamp: bundle.post.timestamp.map(|time| time.to_utc()),
+        timestamp_offset: bundle
+            .post
+            .timestamp
+            .map(|time| time.offset().local_minus_utc()),
+        draft: bundle.post.state == PostState::Draft,
+        unlisted: bundle.post.unlisted,
+        description: bundle.post.description,
+        directory_id: directory_id,
+        thumbnail_source_width: staged
+            .thumbnail_size
+            .map(|size| size.map(|(width, _)| width)),
+        thumbnail_source_height: staged
+            .thumbnail_size
+            .map(|size| size.map(|(_, height)| height)),
+        updated_at: match post_id {
+            Some(_) => None,
+            None => bundle.post.timestamp.map(|time| time.to_utc()),
+        },
+    };
+
+    let id = match old_post {
+        // Update post
+        Some(old_post) => {
+            if new_post.changes(&old_post)
+                || thumbnail != SaveThumbnail::from_post(&old_post)
+            {
+                diesel::update(db::posts::table)
+                    .filter(db::posts::id.eq(old_post.id))
+                    .set((&new_post, &thumbnail))
+                    .execute(connection)?;
+            }
+
+            old_post.id
+        }
+
+        // Insert new post
+        None => diesel::insert_into(db::posts::table)
+            .values((&new_post, &thumbnail))
+            .returning(db::posts::id)
+            .get_result(connection)?,
+    };
+
+    refresh_paths(connection)?;
+
+    save_tags(connection, id, bundle.post.tags)?;
+
+    // Save files.  Existing files are updated in place so they keep their
+    // IDs; only rows that actually change are touched, so that the rest keep
+    // their updated_at.
+    let mut old_files: std::collections::HashMap<i32, db::PostFile> =
+        old_files.into_iter().map(|old| (old.id, old)).collect();
+
+    for (i, (file, file_info)) in
+        (1..).zip(bundle.files.into_iter().zip(staged.file_infos))
+    {
+        let old = file.id.and_then(|file_id| old_files.remove(&file_id));
+
+        match (old, file_info) {
+            // New or replaced file
+            (old, Some(file_info)) => {
+                let new_file = SavePostFile {
+                    id: if file.thumbnail { thumbnail_file_id } else { None },
+                    post_id: id,
+                    order: i,
+                    alt_text: file.alt_text,
+                    media_type: file_info.media_type,
+                    extension: file_info.extension,
+                    kind: file_info.kind.as_str().to_string(),
+                    size: file_info.size,
+                    width: file_info.width,
+                    height: file_info.height,
+                    updated_at: match post_id {
+                        Some(_) => None,
+                        None => new_post.updated_at,
+                    },
+                };
+
+                let file_id = match old {
+                    Some(old) => {
+                        diesel::update(db::post_files::table.find(old.id))
+                            .set(&new_file)
+                            .execute(connection)?;
+                        diesel::delete(db::post_file_variants::table)
+                            .filter(
+                                db::post_file_variants::post_file_id
+                                    .eq(old.id),
+                            )
+                            .execute(connection)?;
+                        old.id
+                    }
+                    None => diesel::insert_into(db::post_files::table)
+                        .values(&new_file)
+                        .returning(db::post_files::id)
+                        .get_result(connection)?,
+                };
+
+                let new_variants: Vec<SavePostFileVariant> = file_info
+                    .variants
+                    .into_iter()
+                    .map(|variant| SavePostFileVariant {
+                        post_file_id: file_id,
+                        media_type: variant.media_type,
+                        extension: variant.extension,
+                        width: variant.width,
+                        height: variant.height,
+                    })
+                    .collect();
+
+                diesel::insert_into(db::post_file_variants::table)
+                    .values(new_variants)
+                    .execute(connection)?;
+            }
+
+            // Existing file, possibly moved, or being presented as a different
+            // kind
+            (Some(old), None) => {
+                let kind = match file.kind {
+                    Some(kind) => {
+                        kind.check(&old.media_type)?;
+                        kind
+                    }
+                    None => FileKind::from_db(&old.kind)?,
+                };
+
+                if old.order != i
+                    || old.alt_text != file.alt_text
+                    || old.kind != kind.as_str()
+                {
+                    diesel::update(db::post_files::table.find(old.id))
+                        .set((
+                            db::post_files::order.eq(i),
+                            db::post_files::alt_text.eq(file.alt_text),
+                            db::post_files::kind.eq(kind.as_str()),
+                        ))
+                        .execute(connection)?;
+                }
+            }
+
+            (None, None) => {
+                return Err(format!("File {i} needs a local_path").into());
+            }
+        }
+    }
+
+    // Anything left over was removed.  That leaves no row behind to show the
+    // edit, so it goes on the post instead.
+    if !old_files.is_empty() {
+        diesel::delete(db::post_files::table)
+            .filter(db::post_files::id.eq_any(old_files.keys()))
+            .execute(connection)?;
+
+        diesel::update(db::posts::table)
+            .filter(db::posts::id.eq(id))
+            .set(db::posts::updated_at.eq(chrono::Utc::now()))
+            .execute(connection)?;
+    }
+
+    Ok(id)
+}
+
+/// Save a post, either edited or new, and add any files and thumbnails.
+fn save_post(
+    input: &str,
+    context: &mut PostContext,
+) -> Result<(), Box<dyn Error>> {
+    // These steps are ordered based on what should error out first
+
+    // Parse edited toml form
+    let mut bundle: EditPostWithFiles = toml::from_str(input)?;
+    let local_paths = bundle
+        .files
+        .iter_mut()
+        .filter_map(|file| file.local_path.as_mut())
+        .chain(bundle.thumbnail.local_path.as_mut());
+    for path in local_paths {
+        *path = context.base_dir.join(&path);
+    }
+    bundle
+        .post
+        .state
+        .check(bundle.post.timestamp.map(|time| time.to_utc()))?;
+    let state = bundle.post.state;
+
+    // Confirm directory ID before making any changes
+    let (directory_id, slug) =
+        find_parent_id(&bundle.post.path, context.connection)?;
+
+    let old_post = context
+        .post_id
+        .map(|id| {
+            db::posts::table
+                .inner_join(db::post_paths::table)
+                .filter(db::posts::id.eq(id))
+                .select(db::Post::as_select())
+                .first(context.connection)
+        })
+        .transpose()?;
+
+    let old_files = match context.post_id {
+        Some(id) => db::post_files::table
+            .filter(db::post_files::post_id.eq(id))
+            .select(db::PostFile::as_select())
+            .load(context.connection)?,
+        None => vec![],
+    };
+
+    // Stage files *before* db stuff so we can avoid eating a post ID if
+    // there's an error here.  The post's current files aren't touched until
+    // the transaction has gone through.
+    let (staging, staged) =
+        stage_files(&bundle, old_post.as_ref(), &old_files, context)?;
+
+    let staging_dir = staging
+        .path()
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or("Expected staging directory name")?
+        .to_string();
+
+    // Save everything to db, along with a note that the staged files need
+    // swapping in, in case that doesn't get to happen
+    let new_id = context.connection.transaction(|connection| {
+        let id = save_post_db(
+            connection,
+            bundle,
+            staged,
+            old_post,
+            old_files,
+            directory_id,
+            slug,
+        )?;
+
+        diesel::insert_into(db::staged_saves::table)
+            .values((
+                db::staged_saves::staging_dir.eq(&staging_dir),
+                db::staged_saves::post_id.eq(id),
+            ))
+            .execute(connection)?;
+
+        Ok::<_, Box<dyn Error>>(id)
+    })?;
+
+    swap_in_files(
+        staging,
+        &context.config.upload_dir.join(new_id.to_string()),
+    )?;
+
+    diesel::delete(db::staged_saves::table.find(&staging_dir))
+        .execute(context.connection)?;
+
+    if state != PostState::Published {
+        let token: String = db::posts::table
+            .find(new_id)
+            .select(db::posts::preview_token)
+            .first(context.connection)?;
+        eprintln!("Preview at {}/preview/{token}", context.config.base_url);
+    }
+
+    Ok(())
+}
+
+/// Create a new post, from the given TOML file if any, or else in an editor.
+fn new_post(
+    connection: &mut diesel::PgConnection,
+    config: cem::CEMConfig,
+    from: Option<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    let mut context = PostContext {
+        post_id: None,
+        connection: connection,
+        config: config,
+        base_dir: PathBuf::new(),
+    };
+
+    if let Some(from) = from {
+        context.base_dir = from_base_dir(&from);
+        return save_from(&from, &mut context, save_post);
+    }
+
+    let empty_post = EditPostWithFiles {
+        post: Default::default(),
+        thumbnail: Default::default(),
+        files: vec![EditPostFile {
+            local_path: Some("".into()),
+            ..Default::default()
+        }],
+    };
+
+    open_in_editor(toml::to_string(&empty_post)?, &mut context, save_post)
+}
+
+/// Load an existing post in the TOML form it's edited in, along with its ID.
+fn load_post(
+    connection: &mut diesel::PgConnection,
+    path: String,
+) -> Result<(i32, EditPostWithFiles), Box<dyn Error>> {
+    let (post, current): (EditPost, db::Post) = db::posts::table
+        .inner_join(db::post_paths::table)
+        .filter(db::post_paths::path.eq(&path))
+        .select((EditPost::as_select(), db::Post::as_select()))
+        .first(connection)
+        .optional()?
+        .ok_or_else(|| format!("Post not found: {path}"))?;
+    let id = current.id;
+
+    let files = db::post_files::table
+        .filter(db::post_files::post_id.eq(id))
+        .order(db::post_files::order)
+        .select((
+            db::post_files::id,
+            db::post_files::kind,
+            db::post_files::alt_text,
+        ))
+        .load::<(i32, String, String)>(connection)?
+        .into_iter()
+        .map(|(id, kind, text)| {
+            Ok(EditPostFile {
+                id: Some(id),
+                local_path: None,
+                kind: Some(FileKind::from_db(&kind)?),
+                alt_text: text,
+                thumbnail: current.thumbnail_file_id == Some(id),
+            })
+        })
+        .collect::<Result<_, Box<dyn Error>>>()?;
+
+    let bundle = EditPostWithFiles {
+        post: post,
+        thumbnail: EditThumbnail::from_post(&current),
+        files: files,
+    };
+
+    Ok((id, bundle))
+}
+
+/// Edit an existing post, replacing it with the given TOML file if any, or
+/// else in an editor.
+///
+/// With a file, failing to find the post is reported as JSON, the same as
+/// anything wrong with the file.
+fn edit_post(
+    connection: &mut diesel::PgConnection,
+    path: String,
+    config: cem::CEMConfig,
+    from: Option<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    let loaded = load_post(connection, path);
+    let (id, bundle) = match from {
+        Some(_) => loaded.map_err(|error| report_json("", error))?,
+        None => loaded?,
+    };
+    let mut context = PostContext {
+        post_id: Some(id),
+        connection: connection,
+        config: config,
+        base_dir: PathBuf::new(),
     };
+
+    match from {
+        Some(from) => {
+            context.base_dir = from_base_dir(&from);
+            save_from(&from, &mut context, save_post)
+        }
+        None => {
+            open_in_editor(toml::to_string(&bundle)?, &mut context, save_post)
+        }
+    }
+}
+
+/// Print an existing post in the TOML form it's edited in.
+fn show_post(
+    connection: &mut diesel::PgConnection,
+    path: String,
+) -> Result<(), Box<dyn Error>> {
+    let (_, bundle) = load_post(connection, path)?;
+    print!("{}", toml::to_string(&bundle)?);
+
+    Ok(())
+}
+
+/// Find a post's ID from its URL path.
+fn find_post_id(
+    path: &str,
+    connection: &mut diesel::PgConnection,
+) -> Result<i32, Box<dyn Error>> {
+    let id = db::post_paths::table
+        .filter(db::post_paths::path.eq(path))
+        .select(db::post_paths::post_id)
+        .first(connection)
+        .optional()?;
+
+    id.ok_or_else(|| format!("Post not found: {path}").into())
+}
+
+/// Move a post to a new path, leaving a redirect at the old one.
+fn move_post(
+    connection: &mut diesel::PgConnection,
+    path: String,
+    new_path: String,
+) -> Result<(), Box<dyn Error>> {
+    connection.transaction(|connection| {
+        let id = find_post_id(&path, connection)?;
+        let (directory_id, slug) = find_parent_id(&new_path, connection)?;
+
+        diesel::update(db::posts::table.find(id))
+            .set((
+                db::posts::directory_id.eq(directory_id),
+                db::posts::slug.eq(slug),
+            ))
+            .execute(connection)?;
+
+        refresh_paths(connection)
+    })
+}
+
+/// Delete a post, its files, and its upload directory, after asking first.
+fn delete_post(
+    connection: &mut diesel::PgConnection,
+    path: String,
+    config: cem::CEMConfig,
+) -> Result<(), Box<dyn Error>> {
+    let id = find_post_id(&path, connection)?;
+
+    if !confirm(&format!("Delete {path} and all its files?"))? {
+        return Err("Exiting at user request".into());
+    }
+
+    connection.transaction(|connection| {
+        delete_post_rows(connection, &[id])?;
+        refresh_paths(connection)
+    })?;
+
+    delete_post_files(&config, &[id])
+}
+
+/// Save a directory, either edited or new.
+fn save_directory(
+    input: &str,
+    context: &mut DirectoryContext,
+) -> Result<(), Box<dyn Error>> {
+    let directory: EditDirectory = toml::from_str(input)?;
+
+    context.connection.transaction(|connection| {
+        let (parent_id, slug) =
+            find_directory_parent_id(&directory.path, connection)?;
+
+        if let (Some(id), Some(parent_id)) = (context.directory_id, parent_id)
+        {
+            check_not_descendant(connection, id, parent_id)?;
+        }
+
+        let new_directory = SaveDirectory {
+            title: directory.title,
+            has_proper_title: directory.has_proper_title,
+            slug: slug,
+            parent_directory_id: parent_id,
+        };
+
+        match context.directory_id {
+            Some(id) => {
+                diesel::update(db::directories::table.find(id))
+                    .set(&new_directory)
+                    .execute(connection)?;
+            }
+            None => {
+                diesel::insert_into(db::directories::table)
+                    .values(&new_directory)
+                    .execute(connection)?;
+            }
+        }
+
+        refresh_paths(connection)
+    })
+}
+
+/// Make sure `parent_id` isn't the directory `id` or anywhere inside it, so
+/// that moving one into the other won't create a loop.
+fn check_not_descendant(
+    connection: &mut diesel::PgConnection,
+    id: i32,
+    parent_id: i32,
+) -> Result<(), Box<dyn Error>> {
+    let mut ancestor_id = Some(parent_id);
+
+    while let Some(current_id) = ancestor_id {
+        if current_id == id {
+            return Err("Can't move a directory inside itself".into());
+        }
+
+        ancestor_id = db::directories::table
+            .find(current_id)
+            .select(db::directories::parent_directory_id)
+            .first(connection)?;
+    }
+
+    Ok(())
+}
+
+/// Create a new directory.
+fn new_directory(
+    connection: &mut diesel::PgConnection,
+) -> Result<(), Box<dyn Error>> {
+    let empty_directory = EditDirectory::default();
     let mut context =
-        PostContext { post_id: None, connection: connection, config: config };
+        DirectoryContext { directory_id: None, connection: connection };
 
-    open_in_editor(toml::to_string(&empty_post)?, &mut context, save_post)
+    open_in_editor(
+        toml::to_string(&empty_directory)?,
+        &mut context,
+        save_directory,
+    )
 }
 
-/// Edit an existing post.
-fn edit_post(
+/// Edit an existing directory.
+fn edit_directory(
+    connection: &mut diesel::PgConnection,
+    path: String,
+) -> Result<(), Box<dyn Error>> {
+    let (id, directory): (i32, EditDirectory) = db::directories::table
+        .inner_join(db::directory_paths::table)
+        .filter(db::directory_paths::path.eq(path))
+        .select((db::directories::id, EditDirectory::as_select()))
+        .first(connection)?;
+
+    let mut context =
+        DirectoryContext { directory_id: Some(id), connection: connection };
+
+    open_in_editor(toml::to_string(&directory)?, &mut context, save_directory)
+}
+
+/// Move a directory into a new parent directory, keeping its slug.
+fn move_directory(
+    connection: &mut diesel::PgConnection,
+    path: String,
+    parent: String,
+) -> Result<(), Box<dyn Error>> {
+    connection.transaction(|connection| {
+        let id = find_directory_id(&path, connection)?;
+
+        let parent_id = match parent.trim_end_matches('/') {
+            "" => None,
+            parent => {
+                let parent_id = find_directory_id(parent, connection)?;
+                check_not_descendant(connection, id, parent_id)?;
+                Some(parent_id)
+            }
+        };
+
+        diesel::update(db::directories::table.find(id))
+            .set(db::directories::parent_directory_id.eq(parent_id))
+            .execute(connection)?;
+
+        refresh_paths(connection)
+    })
+}
+
+/// Delete posts' database rows. Their files are left for `delete_post_files`
+/// to clean up once the transaction has been committed.
+fn delete_post_rows(
+    connection: &mut diesel::PgConnection,
+    post_ids: &[i32],
+) -> Result<(), Box<dyn Error>> {
+    // Variants and tags cascade, but files don't
+    diesel::delete(db::post_files::table)
+        .filter(db::post_files::post_id.eq_any(post_ids))
+        .execute(connection)?;
+    diesel::delete(db::posts::table)
+        .filter(db::posts::id.eq_any(post_ids))
+        .execute(connection)?;
+
+    Ok(())
+}
+
+/// Remove the upload directories of posts that have been deleted.
+fn delete_post_files(
+    config: &cem::CEMConfig,
+    post_ids: &[i32],
+) -> Result<(), Box<dyn Error>> {
+    for id in post_ids {
+        match std::fs::remove_dir_all(config.upload_dir.join(id.to_string())) {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => {
+                return Err(error.into())
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Delete a directory, refusing if it isn't empty unless `recursive` is set.
+fn delete_directory(
     connection: &mut diesel::PgConnection,
     path: String,
+    recursive: bool,
+    config: cem::CEMConfig,
+) -> Result<(), Box<dyn Error>> {
+    let directory_id: i32 = db::directory_paths::table
+        .filter(db::directory_paths::path.eq(&path))
+        .select(db::directory_paths::directory_id)
+        .first(connection)
+        .optional()?
+        .ok_or_else(|| format!("Directory not found: {path}"))?;
+
+    let subtree: Vec<i32> = db::directory_subtrees::table
+        .filter(db::directory_subtrees::directory_id.eq(directory_id))
+        .select(db::directory_subtrees::descendant_id)
+        .load(connection)?;
+
+    let post_ids: Vec<i32> = db::posts::table
+        .filter(db::posts::directory_id.eq_any(&subtree))
+        .select(db::posts::id)
+        .load(connection)?;
+
+    if subtree.len() > 1 || !post_ids.is_empty() {
+        if !recursive {
+            return Err(format!(
+                "{path} contains {} post(s) and {} subdirectory(s); use \
+                 --recursive to delete them too",
+                post_ids.len(),
+                subtree.len() - 1,
+            )
+            .into());
+        }
+
+        let prompt = format!(
+            "Delete {path} along with {} post(s) and {} subdirectory(s)?",
+            post_ids.len(),
+            subtree.len() - 1,
+        );
+        if !confirm(&prompt)? {
+            return Err("Exiting at user request".into());
+        }
+    }
+
+    connection.transaction(|connection| {
+        delete_post_rows(connection, &post_ids)?;
+
+        // All in one statement, so it doesn't matter that some of these
+        // directories are each other's parents
+        diesel::delete(db::directories::table)
+            .filter(db::directories::id.eq_any(&subtree))
+            .execute(connection)?;
+
+        refresh_paths(connection)
+    })?;
+
+    delete_post_files(&config, &post_ids)
+}
+
+/// Record the sizes of any files, and the dimensions of any images and posts'
+/// thumbnail sources, that were uploaded before they were tracked.
+///
+/// None of this is an edit, so it's done with the `updated_at` triggers off.
+/// Otherwise every old post would show as edited today, and feed readers
+/// would see the whole archive change.
+fn backfill_dimensions(
+    connection: &mut diesel::PgConnection,
+    config: cem::CEMConfig,
+) -> Result<(), Box<dyn Error>> {
+    connection.transaction(|connection| {
+        // Disabling triggers is transactional, so they come back on by
+        // themselves if anything fails
+        diesel::sql_query(
+            "alter table post_files disable trigger set_updated_at;",
+        )
+        .execute(connection)?;
+        diesel::sql_query("alter table posts disable trigger set_updated_at;")
+            .execute(connection)?;
+
+        backfill_dimensions_rows(connection, &config)?;
+
+        diesel::sql_query(
+            "alter table post_files enable trigger set_updated_at;",
+        )
+        .execute(connection)?;
+        diesel::sql_query("alter table posts enable trigger set_updated_at;")
+            .execute(connection)?;
+
+        Ok(())
+    })
+}
+
+/// Do the actual work of `backfill_dimensions`.
+fn backfill_dimensions_rows(
+    connection: &mut diesel::PgConnection,
+    config: &cem::CEMConfig,
+) -> Result<(), Box<dyn Error>> {
+    // Files from before sizes were tracked were left at 0
+    let files: Vec<(i32, i32, i32, String)> = db::post_files::table
+        .filter(db::post_files::size.eq(0))
+        .select((
+            db::post_files::id,
+            db::post_files::post_id,
+            db::post_files::order,
+            db::post_files::extension,
+        ))
+        .load(connection)?;
+
+    for (id, post_id, order, extension) in files {
+        let path = config
+            .upload_dir
+            .join(format!("{post_id}/files/{order}.{extension}"));
+        let size: i64 = std::fs::metadata(&path)
+            .map_err(|error| format!("{}: {error}", path.display()))?
+            .len()
+            .try_into()?;
+
+        diesel::update(db::post_files::table.find(id))
+            .set(db::post_files::size.eq(size))
+            .execute(connection)?;
+
+        println!("{}: {size} bytes", path.display());
+    }
+
+    let files: Vec<(i32, i32, i32, String)> = db::post_files::table
+        .filter(db::post_files::kind.eq("image"))
+        .filter(
+            db::post_files::width
+                .is_null()
+                .or(db::post_files::height.is_null()),
+        )
+        .select((
+            db::post_files::id,
+            db::post_files::post_id,
+            db::post_files::order,
+            db::post_files::extension,
+        ))
+        .load(connection)?;
+
+    for (id, post_id, order, extension) in files {
+        let path = config
+            .upload_dir
+            .join(format!("{post_id}/files/{order}.{extension}"));
+        let dimensions = imagesize::size(&path)
+            .map_err(|error| format!("{}: {error}", path.display()))?;
+        let width: i32 = dimensions.width.try_into()?;
+        let height: i32 = dimensions.height.try_into()?;
+
+        diesel::update(db::post_files::table.find(id))
+            .set((
+                db::post_files::width.eq(width),
+                db::post_files::height.eq(height),
+            ))
+            .execute(connection)?;
+
+        println!("{}: {width}x{height}", path.display());
+    }
+
+    // Thumbnails are made from each post's chosen file, or else its first
+    // image.  Posts with a separate thumbnail image or a crop had theirs
+    // recorded when they were saved.
+    let posts = diesel::sql_query(
+        r#"update posts
+            set thumbnail_source_width = post_files.width,
+                thumbnail_source_height = post_files.height
+            from post_files
+            where post_files.post_id = posts.id
+                and post_files.id = (
+                    select id from post_files source
+                    where source.post_id = posts.id
+                        and (
+                            source.id = posts.thumbnail_file_id
+                            or posts.thumbnail_file_id is null
+                                and source.kind = 'image'
+                        )
+                    order by source."order"
+                    limit 1
+                )
+                and posts.thumbnail_extension is null
+                and posts.thumbnail_crop_x is null
+                and posts.thumbnail_focus_x is null
+                and posts.thumbnail_source_width is null;"#,
+    )
+    .execute(connection)?;
+
+    println!("Updated thumbnail dimensions for {posts} post(s)");
+
+    Ok(())
+}
+
+/// Generate variants for any images that were uploaded before variants were
+/// made.
+fn backfill_variants(
+    connection: &mut diesel::PgConnection,
     config: cem::CEMConfig,
 ) -> Result<(), Box<dyn Error>> {
-    let (id, post): (i32, EditPost) = db::posts::table
+    // Images too narrow for any variants won't have had them either; they're
+    // skipped when their width is known, as are types that never get them
+    let files: Vec<(i32, i32, i32, String, String)> = db::post_files::table
+        .left_join(db::post_file_variants::table)
+        .filter(db::post_files::kind.eq("image"))
+        .filter(
+            db::post_files::media_type
+                .ne_all(cem::variants::SKIPPED_MEDIA_TYPES),
+        )
+        .filter(db::post_file_variants::id.is_null())
+        .filter(db::post_files::width.is_null().or(
+            db::post_files::width.gt(cem::variants::VARIANT_WIDTHS[0] as i32),
+        ))
+        .select((
+            db::post_files::id,
+            db::post_files::post_id,
+            db::post_files::order,
+            db::post_files::extension,
+            db::post_files::media_type,
+        ))
+        .load(connection)?;
+
+    for (id, post_id, order, extension, media_type) in files {
+        let post_dir = config.upload_dir.join(post_id.to_string());
+        let path = post_dir.join(format!("files/{order}.{extension}"));
+        let variants_dir = post_dir.join("variants");
+        std::fs::create_dir_all(&variants_dir)?;
+
+        let variants = cem::variants::create_variants(
+            &variants_dir,
+            &path,
+            &media_type,
+            order,
+        )
+        .map_err(|error| format!("{}: {error}", path.display()))?;
+
+        let new_variants = variants
+            .iter()
+            .map(|variant| {
+                Ok(SavePostFileVariant {
+                    post_file_id: id,
+                    media_type: variant.format.media_type().to_string(),
+                    extension: variant.format.extension().to_string(),
+                    width: variant.width.try_into()?,
+                    height: variant.height.try_into()?,
+                })
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+        diesel::insert_into(db::post_file_variants::table)
+            .values(new_variants)
+            .execute(connection)?;
+
+        println!("{}: {} variant(s)", path.display(), variants.len());
+    }
+
+    Ok(())
+}
+
+/// The number of rows in a query result.
+#[derive(diesel::QueryableByName)]
+struct RowCount {
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    count: i64,
+}
+
+/// Report anything in the database or the upload directory that doesn't line
+/// up, fixing what can be fixed if asked to.
+///
+/// Returns an error if any problems are left, so scheduled runs get noticed.
+fn check(
+    connection: &mut diesel::PgConnection,
+    config: &cem::CEMConfig,
+    fix: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut problems = 0;
+
+    // Every file in the database should be uploaded
+    let files: Vec<(i32, i32, String, i32, String, String)> =
+        db::post_files::table
+            .inner_join(
+                db::post_paths::table
+                    .on(db::post_paths::post_id.eq(db::post_files::post_id)),
+            )
+            .order((db::post_paths::path, db::post_files::order))
+            .select((
+                db::post_files::id,
+                db::post_files::post_id,
+                db::post_paths::path,
+                db::post_files::order,
+                db::post_files::extension,
+                db::post_files::kind,
+            ))
+            .load(connection)?;
+
+    for (_, post_id, path, order, extension, _) in &files {
+        let file_path = config
+            .upload_dir
+            .join(post_id.to_string())
+            .join("files")
+            .join(format!("{order}.{extension}"));
+
+        if !file_path.exists() {
+            println!(
+                "{path}: file {order} is missing ({})",
+                file_path.display()
+            );
+            problems += 1;
+        }
+    }
+
+    // Posts with anything to make thumbnails from should have every size
+    let posts: Vec<db::Post> = db::posts::table
         .inner_join(db::post_paths::table)
-        .filter(db::post_paths::path.eq(path))
-        .select((db::posts::id, EditPost::as_select()))
-        .first(connection)?;
+        .order(db::post_paths::path)
+        .select(db::Post::as_select())
+        .load(connection)?;
 
-    let files = db::post_images::table
-        .filter(db::post_images::post_id.eq(id))
-        .order(db::post_images::order)
-        .select(db::post_images::alt_text)
-        .load(connection)?
+    for post in &posts {
+        let post_dir = config.upload_dir.join(post.id.to_string());
+        let source = match &post.thumbnail_extension {
+            Some(extension) => {
+                let source = post_dir.join(format!("thumbnail.{extension}"));
+                if !source.exists() {
+                    println!(
+                        "{}: thumbnail image is missing ({})",
+                        post.path,
+                        source.display()
+                    );
+                    problems += 1;
+                }
+                source
+            }
+            None => {
+                let found =
+                    files.iter().find(|(id, post_id, _, _, _, kind)| {
+                        *post_id == post.id
+                            && match post.thumbnail_file_id {
+                                Some(file_id) => *id == file_id,
+                                None => kind == "image",
+                            }
+                    });
+                let Some((_, _, _, order, extension, _)) = found else {
+                    continue;
+                };
+
+                post_dir.join("files").join(format!("{order}.{extension}"))
+            }
+        };
+
+        let thumbnails_dir = post_dir.join("thumbnails");
+        let missing: Vec<String> = cem::thumbnails::THUMBNAIL_HEIGHTS
+            .iter()
+            .filter(|height| {
+                !thumbnails_dir.join(format!("{height}.png")).exists()
+            })
+            .map(|height| height.to_string())
+            .collect();
+        if missing.is_empty() {
+            continue;
+        }
+
+        println!(
+            "{}: missing thumbnails at {}",
+            post.path,
+            missing.join(", ")
+        );
+        if fix && source.exists() {
+            std::fs::create_dir_all(&thumbnails_dir)?;
+            cem::thumbnails::create_thumbnails(
+                &thumbnails_dir,
+                &source,
+                post.thumbnail_framing(),
+            )?;
+            println!("  Regenerated thumbnails");
+        } else {
+            problems += 1;
+        }
+    }
+
+    // Everything in the upload directory should belong to a post
+    let post_ids: std::collections::HashSet<String> = db::posts::table
+        .select(db::posts::id)
+        .load::<i32>(connection)?
         .into_iter()
-        .map(|text| EditPostFile { alt_text: text, local_path: None })
+        .map(|id| id.to_string())
         .collect();
 
-    let bundle = EditPostWithFiles { post: post, files: files };
-    let mut context = PostContext {
-        post_id: Some(id),
-        connection: connection,
-        config: config,
-    };
+    let mut entries: Vec<std::fs::DirEntry> =
+        std::fs::read_dir(&config.upload_dir)?.collect::<Result<_, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().into_owned();
+
+        if name == STAGING_DIR {
+            if std::fs::read_dir(entry.path())?.next().is_none() {
+                continue;
+            }
+            println!(
+                "{}: left over from unfinished saves",
+                entry.path().display()
+            );
+            if fix {
+                clean_tmp(connection, config)?;
+            } else {
+                problems += 1;
+            }
+        } else if !post_ids.contains(&name) {
+            println!("{}: doesn't belong to any post", entry.path().display());
+            if fix {
+                if entry.file_type()?.is_dir() {
+                    std::fs::remove_dir_all(entry.path())?;
+                } else {
+                    std::fs::remove_file(entry.path())?;
+                }
+                println!("  Removed");
+            } else {
+                problems += 1;
+            }
+        }
+    }
+
+    // The path views should match what they'd be if refreshed now
+    let mut stale = false;
+    for view in ["directory_paths", "post_paths"] {
+        let definition: String = diesel::select(diesel::dsl::sql::<
+            diesel::sql_types::Text,
+        >(&format!(
+            "trim(trailing ';' from pg_get_viewdef('{view}'::regclass))"
+        )))
+        .get_result(connection)?;
+
+        let differences: RowCount = diesel::sql_query(format!(
+            "with fresh as ({definition})
+            select count(*) as count from (
+                (table {view} except table fresh)
+                union all
+                (table fresh except table {view})
+            ) as differences"
+        ))
+        .get_result(connection)?;
+
+        if differences.count > 0 {
+            println!("{view}: out of date by {} row(s)", differences.count);
+            stale = true;
+        }
+    }
+    if stale {
+        if fix {
+            refresh_paths(connection)?;
+            println!("  Refreshed views");
+        } else {
+            problems += 1;
+        }
+    }
+
+    if problems > 0 {
+        return Err(format!("{problems} problem(s) left").into());
+    }
+
+    println!("Everything checks out");
+    Ok(())
+}
 
-    open_in_editor(toml::to_string(&bundle)?, &mut context, save_post)
+fn main() -> std::process::ExitCode {
+    match run() {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(error) => {
+            if !error.is::<AlreadyReported>() {
+                eprintln!("Error: {error:?}");
+            }
+            std::process::ExitCode::FAILURE
+        }
+    }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
+/// Run the subcommand given on the command line.
+fn run() -> Result<(), Box<dyn Error>> {
     let cli = CLI::parse();
     let config = rocket::Config::figment();
     let cem_config: cem::CEMConfig = config.extract_inner("cem")?;
+    cem_config.set_display_timezone();
     let db_url: String = config.extract_inner("databases.cem.url")?;
     let mut connection = diesel::PgConnection::establish(&db_url)?;
 
     match cli.command {
-        Command::PostNew => new_post(&mut connection, cem_config),
-        Command::PostEdit { path } => {
-            edit_post(&mut connection, path, cem_config)
+        Command::PostNew { from } => {
+            new_post(&mut connection, cem_config, from)
+        }
+        Command::PostEdit { path, from } => {
+            edit_post(&mut connection, path, cem_config, from)
+        }
+        Command::PostShow { path } => show_post(&mut connection, path),
+        Command::PostDelete { path } => {
+            delete_post(&mut connection, path, cem_config)
+        }
+        Command::PostMove { path, new_path } => {
+            move_post(&mut connection, path, new_path)
+        }
+        Command::DirNew => new_directory(&mut connection),
+        Command::DirEdit { path } => edit_directory(&mut connection, path),
+        Command::DirMove { path, parent } => {
+            move_directory(&mut connection, path, parent)
+        }
+        Command::DirDelete { path, recursive } => {
+            delete_directory(&mut connection, path, recursive, cem_config)
         }
+        Command::BackfillDimensions => {
+            backfill_dimensions(&mut connection, cem_config)
+        }
+        Command::BackfillVariants => {
+            backfill_variants(&mut connection, cem_config)
+        }
+        Command::CleanTmp => clean_tmp(&mut connection, &cem_config),
+        Command::Check { fix } => check(&mut connection, &cem_config, fix),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/thumbnails")
+            .join(name)
+    }
+
+    #[test]
+    fn avif_images_need_another_thumbnail_source() {
+        let avif = fixture("gradient.avif");
+        let png = fixture("gradient.png");
+        let files = |thumbnail| {
+            vec![
+                EditPostFile {
+                    local_path: Some(avif.clone()),
+                    ..Default::default()
+                },
+                EditPostFile {
+                    local_path: Some(png.clone()),
+                    thumbnail: thumbnail,
+                    ..Default::default()
+                },
+            ]
+        };
+        let file_infos = || {
+            vec![
+                Some(detect_file_info(&avif, None).unwrap()),
+                Some(detect_file_info(&png, None).unwrap()),
+            ]
+        };
+
+        // Still shown as an image...
+        assert!(file_infos()[0].as_ref().unwrap().kind == FileKind::Image);
+
+        // ...but not used for thumbnails unless something else is chosen
+        assert!(
+            find_thumbnail_file(&files(false), &file_infos(), &[]).is_err()
+        );
+        assert_eq!(
+            find_thumbnail_file(&files(true), &file_infos(), &[]).unwrap(),
+            Some((1, None))
+        );
+    }
+
+    #[test]
+    fn supported_images_decode() {
+        let info = detect_file_info(&fixture("gradient.png"), None).unwrap();
+        assert!(info.kind == FileKind::Image);
+        assert!(SUPPORTED_IMAGE_TYPES.contains(&info.media_type.as_str()));
+        assert!(cem::thumbnails::open_image(&fixture("gradient.png")).is_ok());
+    }
+
+    #[derive(serde::Deserialize, serde::Serialize)]
+    struct Timestamped {
+        #[serde(
+            serialize_with = "chrono_to_toml",
+            deserialize_with = "toml_to_chrono"
+        )]
+        timestamp: Option<chrono::DateTime<chrono::FixedOffset>>,
+    }
+
+    #[test]
+    fn timestamps_round_trip() {
+        let input = "timestamp = 2024-05-01T10:00:00-04:00\n";
+        let parsed: Timestamped = toml::from_str(input).unwrap();
+
+        // Stored the way save_post_db stores it, and loaded back
+        let time = parsed.timestamp.unwrap();
+        let stored = StoredTimestamp(
+            time.to_utc(),
+            Some(time.offset().local_minus_utc()),
+        );
+        let loaded = Timestamped { timestamp: stored.into() };
+
+        assert_eq!(toml::to_string(&loaded).unwrap(), input);
+    }
+
+    #[test]
+    fn timestamps_without_offsets_use_display_time_zone() {
+        let time = "2024-05-01T14:00:00Z".parse().unwrap();
+        let loaded =
+            Timestamped { timestamp: StoredTimestamp(time, None).into() };
+
+        assert_eq!(
+            toml::to_string(&loaded).unwrap(),
+            "timestamp = 2024-05-01T14:00:00Z\n"
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a migrated database in CEM_TEST_DATABASE_URL"]
+    fn backfills_leave_updated_at_alone() {
+        let db_url = std::env::var("CEM_TEST_DATABASE_URL").unwrap();
+        let mut connection = diesel::PgConnection::establish(&db_url).unwrap();
+        let upload_dir = tempfile::tempdir().unwrap();
+        let config = cem::CEMConfig {
+            upload_dir: upload_dir.path().to_path_buf(),
+            base_url: String::new(),
+            thumbnail_heights: Vec::new(),
+            display_timezone: None,
+        };
+
+        connection.test_transaction::<_, Box<dyn Error>, _>(|connection| {
+            // The trigger only fires on updates, so these keep this time
+            let updated_at = "2020-01-01T00:00:00Z";
+            let post_id = diesel::sql_query(
+                r#"with directory as (
+                    insert into directories (title, slug)
+                        values ('Test', 'backfill-test') returning id
+                ), post as (
+                    insert into posts (
+                        title, slug, directory_id, description, updated_at
+                    )
+                        select 'Test', 'test', id, '', $1::timestamptz
+                        from directory
+                        returning id
+                ), file as (
+                    insert into post_files (
+                        post_id, "order", alt_text, media_type, extension,
+                        kind, size, updated_at
+                    )
+                        select id, 0, '', 'image/png', 'png', 'image', 0,
+                            $1::timestamptz
+                        from post
+                )
+                select id from post;"#,
+            )
+            .bind::<diesel::sql_types::Text, _>(updated_at)
+            .get_result::<PostId>(connection)?
+            .id;
+
+            // Postgres won't alter a table with deferred checks still to run
+            diesel::sql_query("set constraints all immediate;")
+                .execute(connection)?;
+
+            let files_dir = upload_dir.path().join(format!("{post_id}/files"));
+            std::fs::create_dir_all(&files_dir)?;
+            std::fs::copy(fixture("gradient.png"), files_dir.join("0.png"))?;
+
+            backfill_dimensions(connection, config)?;
+
+            let updated_at: chrono::DateTime<chrono::Utc> =
+                updated_at.parse()?;
+            let post: (Option<i32>, chrono::DateTime<chrono::Utc>) =
+                db::posts::table
+                    .find(post_id)
+                    .select((
+                        db::posts::thumbnail_source_width,
+                        db::posts::updated_at,
+                    ))
+                    .first(connection)?;
+            let file: (i64, Option<i32>, chrono::DateTime<chrono::Utc>) =
+                db::post_files::table
+                    .filter(db::post_files::post_id.eq(post_id))
+                    .select((
+                        db::post_files::size,
+                        db::post_files::width,
+                        db::post_files::updated_at,
+                    ))
+                    .first(connection)?;
+
+            // Filled in, but not counted as edits
+            assert!(post.0.is_some());
+            assert!(file.0 > 0 && file.1.is_some());
+            assert_eq!(post.1, updated_at);
+            assert_eq!(file.2, updated_at);
+            Ok(())
+        });
+    }
+
+    #[derive(diesel::QueryableByName)]
+    struct PostId {
+        #[diesel(sql_type = diesel::sql_types::Integer)]
+        id: i32,
     }
 }
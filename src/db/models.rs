@@ -12,6 +12,8 @@ pub struct Directory {
     /// dash-separated
     pub slug: String,
     pub parent_directory_id: Option<i32>,
+    /// The last time this directory was changed
+    pub updated_at: chrono::DateTime<chrono::Utc>,
 
     /// The full path for this directory, including all parent directories
     // Requires joining to the directory_paths view, which is fine; I always
@@ -40,9 +42,40 @@ pub struct Post {
     pub slug: String,
     /// The time this post was made, which may be backdated to the time it was
     /// originally made on another site
-    pub timestamp: chrono::NaiveDateTime,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// The offset from UTC, in seconds, that `timestamp` was entered with, if
+    /// known
+    pub timestamp_offset: Option<i32>,
     pub directory_id: i32,
     pub description: String,
+    /// The dimensions of the part of the image this post's thumbnails show,
+    /// if known; see `thumbnail_width`
+    pub thumbnail_source_width: Option<i32>,
+    pub thumbnail_source_height: Option<i32>,
+    /// The ID of the file this post's thumbnails are made from, if not its
+    /// first image
+    pub thumbnail_file_id: Option<i32>,
+    /// The extension of a separate image this post's thumbnails are made from
+    /// instead of any of its files, saved next to them as
+    /// `thumbnail.<extension>`
+    pub thumbnail_extension: Option<String>,
+    /// Where to crop the thumbnail source; see `thumbnail_framing`
+    pub thumbnail_crop_x: Option<i32>,
+    pub thumbnail_crop_y: Option<i32>,
+    pub thumbnail_crop_width: Option<i32>,
+    pub thumbnail_crop_height: Option<i32>,
+    /// What to centre thumbnails on, instead of cropping them exactly; see
+    /// `thumbnail_framing`
+    pub thumbnail_focus_x: Option<i32>,
+    pub thumbnail_focus_y: Option<i32>,
+    /// If true, this post is only visible at its preview URL; see `published`
+    pub draft: bool,
+    /// If true, this post is left out of listings but can still be visited
+    /// directly; see `listed`
+    pub unlisted: bool,
+    /// The last time this post was changed.  Posts start out with this set to
+    /// `timestamp`, so it's only later if they've been edited since.
+    pub updated_at: chrono::DateTime<chrono::Utc>,
 
     /// The full path for this post, including all parent directories
     // Requires joining to the post_paths view, which is fine; I always want
@@ -52,11 +85,76 @@ pub struct Post {
     pub path: String,
 }
 
-/// A file attached to a post.
-///
-/// TODO: I plan to rename this to `PostFile`, since I may want to post audio
-/// or video or attach other files to blog posts.  But for now the name is
-/// `PostImage` and it is in fact just images.
+/// The type of `Post::published`.
+pub type Published = diesel::dsl::And<
+    diesel::dsl::Eq<super::posts::draft, bool>,
+    diesel::dsl::LtEq<super::posts::timestamp, diesel::dsl::now>,
+>;
+
+/// The type of `Post::listed`.
+pub type Listed =
+    diesel::dsl::And<Published, diesel::dsl::Eq<super::posts::unlisted, bool>>;
+
+impl Post {
+    /// A filter for posts that are visible to the public: not drafts, and not
+    /// scheduled for some time that hasn't come yet.
+    pub fn published() -> Published {
+        use diesel::{BoolExpressionMethods, ExpressionMethods};
+
+        super::posts::draft
+            .eq(false)
+            .and(super::posts::timestamp.le(diesel::dsl::now))
+    }
+
+    /// A filter for posts that should show up in listings, feeds, and
+    /// prev/next links: published, and not unlisted.
+    pub fn listed() -> Listed {
+        use diesel::{BoolExpressionMethods, ExpressionMethods};
+
+        Post::published().and(super::posts::unlisted.eq(false))
+    }
+
+    /// Which part of its source image this post's thumbnails show.
+    pub fn thumbnail_framing(&self) -> crate::thumbnails::Framing {
+        use crate::thumbnails::{Crop, Focus, Framing};
+
+        let pixels = |value: Option<i32>| value?.try_into().ok();
+
+        if let (Some(x), Some(y), Some(width), Some(height)) = (
+            pixels(self.thumbnail_crop_x),
+            pixels(self.thumbnail_crop_y),
+            pixels(self.thumbnail_crop_width),
+            pixels(self.thumbnail_crop_height),
+        ) {
+            Framing::Crop(Crop { x: x, y: y, width: width, height: height })
+        } else if let (Some(x), Some(y)) =
+            (pixels(self.thumbnail_focus_x), pixels(self.thumbnail_focus_y))
+        {
+            Framing::Focus(Focus { x: x, y: y })
+        } else {
+            Framing::Whole
+        }
+    }
+
+    /// The width of this post's thumbnail at the given height, if known.
+    ///
+    /// Thumbnails keep their source image's aspect ratio, rounded the same
+    /// way as in `cem::thumbnails::scale_to_height`.
+    ///
+    /// (Generic because Askama passes macro arguments by reference.)
+    pub fn thumbnail_width(
+        &self,
+        height: impl std::borrow::Borrow<i32>,
+    ) -> Option<i32> {
+        let width = self.thumbnail_source_width? as f64;
+        let source_height = self.thumbnail_source_height? as f64;
+        let height = *height.borrow() as f64;
+
+        Some(((width * height / source_height).round() as i32).max(1))
+    }
+}
+
+/// A file attached to a post: an image, audio, video, or a download.
 #[derive(
     diesel::Queryable,
     diesel::Selectable,
@@ -64,10 +162,73 @@ pub struct Post {
     diesel::Associations,
 )]
 #[diesel(belongs_to(Post, foreign_key=post_id))]
-#[diesel(table_name = super::post_images)]
-pub struct PostImage {
+#[diesel(table_name = super::post_files)]
+pub struct PostFile {
     pub id: i32,
     pub post_id: i32,
     pub order: i32,
+    /// Alt text for images, or a text description for other kinds of file;
+    /// downloads use it as their link text
     pub alt_text: String,
+    /// The file's MIME type, e.g. `image/png`
+    pub media_type: String,
+    /// The extension the file is saved with in the upload directory, without
+    /// the leading dot
+    pub extension: String,
+    /// How the file is presented: `image`, `audio`, `video`, or `download`
+    pub kind: String,
+    /// The file's size in bytes
+    pub size: i64,
+    /// The file's dimensions in pixels, if it's an image
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    /// The last time this file was replaced or had its alt text or kind
+    /// changed
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A smaller copy of a full-size post image in a modern format, for use in
+/// `srcset`.
+#[derive(
+    diesel::Queryable,
+    diesel::Selectable,
+    diesel::Identifiable,
+    diesel::Associations,
+)]
+#[diesel(belongs_to(PostFile, foreign_key=post_file_id))]
+#[diesel(table_name = super::post_file_variants)]
+pub struct PostFileVariant {
+    pub id: i32,
+    pub post_file_id: i32,
+    pub media_type: String,
+    pub extension: String,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// A tag that can be applied to any number of posts, alongside the directory
+/// a post lives in.
+#[derive(diesel::Queryable, diesel::Selectable, diesel::Identifiable)]
+#[diesel(table_name = super::tags)]
+pub struct Tag {
+    pub id: i32,
+    pub name: String,
+    /// This tag's name as it appears in the URL, lowercase and dash-separated
+    pub slug: String,
+}
+
+/// The link between a post and one of its tags.
+#[derive(
+    diesel::Queryable,
+    diesel::Selectable,
+    diesel::Identifiable,
+    diesel::Associations,
+)]
+#[diesel(belongs_to(Post, foreign_key=post_id))]
+#[diesel(belongs_to(Tag, foreign_key=tag_id))]
+#[diesel(primary_key(post_id, tag_id))]
+#[diesel(table_name = super::post_tags)]
+pub struct PostTag {
+    pub post_id: i32,
+    pub tag_id: i32,
 }
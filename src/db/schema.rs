@@ -1,5 +1,11 @@
 // @generated automatically by Diesel CLI.
 
+pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "tsvector", schema = "pg_catalog"))]
+    pub struct Tsvector;
+}
+
 diesel::table! {
     directories (id) {
         id -> Int4,
@@ -7,31 +13,118 @@ diesel::table! {
         slug -> Text,
         parent_directory_id -> Nullable<Int4>,
         has_proper_title -> Bool,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    post_file_variants (id) {
+        id -> Int4,
+        post_file_id -> Int4,
+        media_type -> Text,
+        extension -> Text,
+        width -> Int4,
+        height -> Int4,
     }
 }
 
 diesel::table! {
-    post_images (id) {
+    use diesel::sql_types::*;
+    use super::sql_types::Tsvector;
+
+    post_files (id) {
         id -> Int4,
         post_id -> Int4,
         order -> Int4,
         alt_text -> Text,
+        media_type -> Text,
+        extension -> Text,
+        kind -> Text,
+        size -> Int8,
+        width -> Nullable<Int4>,
+        height -> Nullable<Int4>,
+        search_vector -> Tsvector,
+        updated_at -> Timestamptz,
     }
 }
 
 diesel::table! {
+    post_tags (post_id, tag_id) {
+        post_id -> Int4,
+        tag_id -> Int4,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::Tsvector;
+
     posts (id) {
         id -> Int4,
         title -> Text,
         slug -> Text,
-        timestamp -> Timestamp,
+        timestamp -> Timestamptz,
         directory_id -> Int4,
         description -> Text,
         has_proper_title -> Bool,
+        thumbnail_source_width -> Nullable<Int4>,
+        thumbnail_source_height -> Nullable<Int4>,
+        draft -> Bool,
+        preview_token -> Text,
+        unlisted -> Bool,
+        search_vector -> Tsvector,
+        updated_at -> Timestamptz,
+        timestamp_offset -> Nullable<Int4>,
+        thumbnail_extension -> Nullable<Text>,
+        thumbnail_crop_x -> Nullable<Int4>,
+        thumbnail_crop_y -> Nullable<Int4>,
+        thumbnail_crop_width -> Nullable<Int4>,
+        thumbnail_crop_height -> Nullable<Int4>,
+        thumbnail_focus_x -> Nullable<Int4>,
+        thumbnail_focus_y -> Nullable<Int4>,
+        thumbnail_file_id -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    redirects (path) {
+        path -> Text,
+        post_id -> Nullable<Int4>,
+        directory_id -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    staged_saves (staging_dir) {
+        staging_dir -> Text,
+        post_id -> Int4,
+    }
+}
+
+diesel::table! {
+    tags (id) {
+        id -> Int4,
+        name -> Text,
+        slug -> Text,
     }
 }
 
-diesel::joinable!(post_images -> posts (post_id));
+diesel::joinable!(post_file_variants -> post_files (post_file_id));
+diesel::joinable!(post_files -> posts (post_id));
+diesel::joinable!(post_tags -> posts (post_id));
+diesel::joinable!(post_tags -> tags (tag_id));
 diesel::joinable!(posts -> directories (directory_id));
+diesel::joinable!(redirects -> directories (directory_id));
+diesel::joinable!(redirects -> posts (post_id));
+diesel::joinable!(staged_saves -> posts (post_id));
 
-diesel::allow_tables_to_appear_in_same_query!(directories, post_images, posts,);
+diesel::allow_tables_to_appear_in_same_query!(
+    directories,
+    post_file_variants,
+    post_files,
+    post_tags,
+    posts,
+    redirects,
+    staged_saves,
+    tags,
+);
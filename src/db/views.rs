@@ -5,7 +5,10 @@
 //! so these have to live in a separate module so as not to get overwritten
 //! every time the main schema changes.
 
-use super::schema::{directories, post_images, posts};
+use super::schema::{
+    directories, post_file_variants, post_files, post_tags, posts, redirects,
+    staged_saves, tags,
+};
 
 diesel::table! {
     directory_paths (directory_id) {
@@ -14,6 +17,13 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    directory_subtrees (directory_id, descendant_id) {
+        directory_id -> Int4,
+        descendant_id -> Int4,
+    }
+}
+
 diesel::table! {
     post_paths (post_id) {
         post_id -> Int4,
@@ -28,11 +38,44 @@ diesel::joinable!(post_paths -> posts (post_id));
 // associate two tables that have already been associated in schema.rs, it will
 // complain
 diesel::allow_tables_to_appear_in_same_query!(directory_paths, post_paths);
+diesel::allow_tables_to_appear_in_same_query!(
+    directory_paths,
+    directory_subtrees
+);
+diesel::allow_tables_to_appear_in_same_query!(directory_subtrees, post_paths);
 
 diesel::allow_tables_to_appear_in_same_query!(directory_paths, directories);
 diesel::allow_tables_to_appear_in_same_query!(directory_paths, posts);
-diesel::allow_tables_to_appear_in_same_query!(directory_paths, post_images);
+diesel::allow_tables_to_appear_in_same_query!(directory_paths, post_files);
+diesel::allow_tables_to_appear_in_same_query!(
+    directory_paths,
+    post_file_variants
+);
+diesel::allow_tables_to_appear_in_same_query!(directory_paths, post_tags);
+diesel::allow_tables_to_appear_in_same_query!(directory_paths, redirects);
+diesel::allow_tables_to_appear_in_same_query!(directory_paths, staged_saves);
+diesel::allow_tables_to_appear_in_same_query!(directory_paths, tags);
+
+diesel::allow_tables_to_appear_in_same_query!(directory_subtrees, directories);
+diesel::allow_tables_to_appear_in_same_query!(directory_subtrees, posts);
+diesel::allow_tables_to_appear_in_same_query!(directory_subtrees, post_files);
+diesel::allow_tables_to_appear_in_same_query!(
+    directory_subtrees,
+    post_file_variants
+);
+diesel::allow_tables_to_appear_in_same_query!(directory_subtrees, post_tags);
+diesel::allow_tables_to_appear_in_same_query!(directory_subtrees, redirects);
+diesel::allow_tables_to_appear_in_same_query!(
+    directory_subtrees,
+    staged_saves
+);
+diesel::allow_tables_to_appear_in_same_query!(directory_subtrees, tags);
 
 diesel::allow_tables_to_appear_in_same_query!(post_paths, directories);
 diesel::allow_tables_to_appear_in_same_query!(post_paths, posts);
-diesel::allow_tables_to_appear_in_same_query!(post_paths, post_images);
+diesel::allow_tables_to_appear_in_same_query!(post_paths, post_files);
+diesel::allow_tables_to_appear_in_same_query!(post_paths, post_file_variants);
+diesel::allow_tables_to_appear_in_same_query!(post_paths, post_tags);
+diesel::allow_tables_to_appear_in_same_query!(post_paths, redirects);
+diesel::allow_tables_to_appear_in_same_query!(post_paths, staged_saves);
+diesel::allow_tables_to_appear_in_same_query!(post_paths, tags);
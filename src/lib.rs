@@ -1,4 +1,6 @@
 pub mod db;
+pub mod thumbnails;
+pub mod variants;
 
 /// Config specific to Cat's Eye Marble.
 ///
@@ -8,4 +10,45 @@ pub mod db;
 pub struct CEMConfig {
     pub upload_dir: std::path::PathBuf,
     pub base_url: String,
+    /// The thumbnail heights the site will serve, generating them on request
+    /// if they don't exist yet
+    #[serde(default = "default_thumbnail_heights")]
+    pub thumbnail_heights: Vec<i32>,
+    /// The time zone times are shown in on the site and in the editor, e.g.
+    /// `America/Toronto`; UTC if not given
+    #[serde(default)]
+    pub display_timezone: Option<chrono_tz::Tz>,
+}
+
+impl CEMConfig {
+    /// Make this config's display time zone the one `display_timezone`
+    /// returns.  Only the first call has any effect.
+    pub fn set_display_timezone(&self) {
+        let timezone = self.display_timezone.unwrap_or(chrono_tz::UTC);
+        let _ = DISPLAY_TIMEZONE.set(timezone);
+    }
+}
+
+/// The default for `CEMConfig::thumbnail_heights`: the ones the CLI makes.
+fn default_thumbnail_heights() -> Vec<i32> {
+    thumbnails::THUMBNAIL_HEIGHTS.iter().map(|&height| height as i32).collect()
+}
+
+/// The time zone set by `CEMConfig::set_display_timezone`.
+static DISPLAY_TIMEZONE: std::sync::OnceLock<chrono_tz::Tz> =
+    std::sync::OnceLock::new();
+
+/// The time zone to show times in.
+///
+/// This is global rather than passed around with the config because
+/// templates and serde helpers need it too, and neither can be handed it.
+pub fn display_timezone() -> chrono_tz::Tz {
+    DISPLAY_TIMEZONE.get().copied().unwrap_or(chrono_tz::UTC)
+}
+
+/// Convert a time to the display time zone.
+pub fn local_time(
+    time: &chrono::DateTime<chrono::Utc>,
+) -> chrono::DateTime<chrono_tz::Tz> {
+    time.with_timezone(&display_timezone())
 }
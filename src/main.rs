@@ -2,8 +2,9 @@ use rocket_db_pools::diesel::prelude::*;
 use rocket_db_pools::Database as _;
 
 use cem::db::{
-    directories, directory_paths, post_images, post_paths, posts, Directory,
-    Post, PostImage,
+    directories, directory_paths, directory_subtrees, post_file_variants,
+    post_files, post_paths, posts, redirects, tags, Directory, Post, PostFile,
+    PostFileVariant, PostTag, Tag,
 };
 
 /// A cachebust timestamp used in the URL of static files.
@@ -17,11 +18,19 @@ fn cachebust() -> i64 {
     *CACHEBUST
 }
 
-/// Midnight on the date the site launched (2024-08-25).
+/// Return the current time, in the same form as post timestamps.
+fn now() -> chrono::DateTime<chrono::Utc> {
+    chrono::Utc::now()
+}
+
+/// How many of the latest posts are included in each feed.
+const FEED_LENGTH: i64 = 20;
+
+/// Midnight UTC on the date the site launched (2024-08-25).
 ///
 /// Anything dated earlier is labelled "Originally posted on..." and doesn't
 /// show up in the Atom feed.
-const SITE_LAUNCH: chrono::naive::NaiveDateTime = {
+const SITE_LAUNCH: chrono::DateTime<chrono::Utc> = {
     // expect/unwrap in const aren't available yet at the time of writing
     let Some(date) = chrono::naive::NaiveDate::from_ymd_opt(2024, 8, 25)
     else {
@@ -30,7 +39,7 @@ const SITE_LAUNCH: chrono::naive::NaiveDateTime = {
     let Some(datetime) = date.and_hms_opt(0, 0, 0) else {
         panic!("Expected launch datetime")
     };
-    datetime
+    datetime.and_utc()
 };
 
 /// An item to be included in the heirarchy of parent links above the page
@@ -40,13 +49,74 @@ struct Breadcrumb {
     label: String,
 }
 
+/// A link to another page of a paginated listing, or to the archive page for
+/// a neighbouring year or month.
+struct PageLink {
+    path: String,
+    label: String,
+}
+
+/// A post file along with its responsive variants.
+type FileWithVariants = (PostFile, Vec<PostFileVariant>);
+
+/// When a post was last edited after it went up, counting changes to its
+/// files, if it has been.
+fn last_edited(
+    post: &Post,
+    files: &[FileWithVariants],
+) -> Option<chrono::DateTime<chrono::Utc>> {
+    let updated = files
+        .iter()
+        .map(|(file, _)| file.updated_at)
+        .chain([post.updated_at])
+        .max()?;
+
+    (updated > post.timestamp).then_some(updated)
+}
+
+/// When a post was last changed, for feeds: when it was last edited, or
+/// otherwise when it went up.
+fn post_updated(
+    post: &Post,
+    files: &[FileWithVariants],
+) -> chrono::DateTime<chrono::Utc> {
+    last_edited(post, files).unwrap_or(post.timestamp)
+}
+
+/// Build a `srcset` listing a post file's variants in the given format.
+///
+/// `url_prefix` works the same as in the `post_file` template macro.
+fn srcset(
+    post: &Post,
+    file: &PostFile,
+    variants: &[PostFileVariant],
+    media_type: &str,
+    url_prefix: &str,
+) -> String {
+    variants
+        .iter()
+        .filter(|variant| variant.media_type == media_type)
+        .map(|variant| {
+            format!(
+                "{url_prefix}{}/files/{}/{}.{} {}w",
+                post.path,
+                file.order,
+                variant.width,
+                variant.extension,
+                variant.width
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 /// The template for the `index` route.
 #[derive(askama::Template)]
 #[template(path = "index.html")]
 struct IndexTemplate {
     base_url: String,
     posts: Vec<Post>,
-    files: Vec<PostImage>,
+    files: Vec<FileWithVariants>,
 }
 
 /// The template for the `feed` route.
@@ -54,16 +124,245 @@ struct IndexTemplate {
 #[template(path = "feed.xml")]
 struct FeedTemplate {
     posts: Vec<Post>,
-    files: Vec<Vec<PostImage>>,
+    files: Vec<Vec<FileWithVariants>>,
     base_url: String,
     domain: String,
+    /// The part of the feed's tag URI after the date, e.g. `feed`
+    feed_id: String,
+    title: String,
+    /// The path of the feed itself
+    path: String,
+    /// The path of the page the feed follows
+    alternate_path: String,
+    /// The latest time any entry in the feed was posted or edited
+    updated: Option<chrono::DateTime<chrono::Utc>>,
+    /// What to send as Last-Modified; see `feeds_last_modified`
+    last_modified: Option<chrono::DateTime<chrono::Utc>>,
+    /// True if this is an archive document (RFC 5005) rather than the feed
+    /// people subscribe to
+    archive: bool,
+    /// The paths of the neighbouring archive documents, if any
+    prev_archive_path: Option<String>,
+    next_archive_path: Option<String>,
 }
 
-/// A wrapper around the Atom feed template to set the Content-Type.
-#[derive(rocket::Responder)]
-#[response(content_type = "application/atom+xml")]
+/// The same feed as `FeedTemplate`, in RSS 2.0.
+#[derive(askama::Template)]
+#[template(path = "rss.xml")]
+struct RssTemplate<'a> {
+    feed: &'a FeedTemplate,
+}
+
+/// The body of a feed entry on its own, for formats that aren't templates.
+#[derive(askama::Template)]
+#[template(path = "feed_content.html")]
+struct FeedContentTemplate<'a> {
+    post: &'a Post,
+    files: &'a [FileWithVariants],
+    base_url: &'a str,
+}
+
+/// A JSON Feed 1.1 document.
+#[derive(serde::Serialize)]
+struct JsonFeed {
+    version: &'static str,
+    title: String,
+    home_page_url: String,
+    feed_url: String,
+    authors: Vec<JsonFeedAuthor>,
+    language: &'static str,
+    items: Vec<JsonFeedItem>,
+}
+
+#[derive(serde::Serialize)]
+struct JsonFeedAuthor {
+    name: &'static str,
+}
+
+#[derive(serde::Serialize)]
+struct JsonFeedItem {
+    id: String,
+    url: String,
+    /// Plain text, so proper titles go without italics
+    title: String,
+    content_html: String,
+    /// The first image, if any, for readers that show a preview
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<String>,
+    date_published: String,
+    date_modified: String,
+    attachments: Vec<JsonFeedAttachment>,
+}
+
+#[derive(serde::Serialize)]
+struct JsonFeedAttachment {
+    url: String,
+    mime_type: String,
+    /// The file's alt text
+    title: String,
+    size_in_bytes: i64,
+}
+
+/// A rendered feed, along with what feed readers need to skip downloading it
+/// again when nothing's changed.
 struct FeedResponse {
-    template: FeedTemplate,
+    body: String,
+    content_type: rocket::http::ContentType,
+    /// A hash of the body, so it changes whenever anything in the feed does
+    etag: String,
+    last_modified: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl FeedResponse {
+    /// Render an Atom feed.
+    fn atom(template: &FeedTemplate) -> Result<Self, SiteError> {
+        Ok(FeedResponse::new(
+            askama::Template::render(template)?,
+            rocket::http::ContentType::new("application", "atom+xml"),
+            template.last_modified,
+        ))
+    }
+
+    /// Render an RSS 2.0 feed.
+    fn rss(template: &FeedTemplate) -> Result<Self, SiteError> {
+        Ok(FeedResponse::new(
+            askama::Template::render(&RssTemplate { feed: template })?,
+            rocket::http::ContentType::new("application", "rss+xml"),
+            template.last_modified,
+        ))
+    }
+
+    /// Render a JSON Feed.
+    fn json(template: &FeedTemplate) -> Result<Self, SiteError> {
+        let base_url = &template.base_url;
+
+        let mut items = Vec::new();
+        for (post, files) in std::iter::zip(&template.posts, &template.files) {
+            let file_url = |file: &PostFile| {
+                format!("{base_url}{}/files/{}", post.path, file.order)
+            };
+
+            let content = FeedContentTemplate {
+                post: post,
+                files: files,
+                base_url: base_url,
+            };
+
+            items.push(JsonFeedItem {
+                id: format!("tag:{},2024:post/{}", template.domain, post.id),
+                url: format!("{base_url}{}", post.path),
+                title: post.title.clone(),
+                content_html: askama::Template::render(&content)?,
+                image: files
+                    .iter()
+                    .find(|(file, _)| file.kind == "image")
+                    .map(|(file, _)| file_url(file)),
+                date_published: post
+                    .timestamp
+                    .format("%Y-%m-%dT%H:%M:%SZ")
+                    .to_string(),
+                date_modified: post_updated(post, files)
+                    .format("%Y-%m-%dT%H:%M:%SZ")
+                    .to_string(),
+                attachments: files
+                    .iter()
+                    .map(|(file, _)| JsonFeedAttachment {
+                        url: file_url(file),
+                        mime_type: file.media_type.clone(),
+                        title: file.alt_text.clone(),
+                        size_in_bytes: file.size,
+                    })
+                    .collect(),
+            });
+        }
+
+        let feed = JsonFeed {
+            version: "https://jsonfeed.org/version/1.1",
+            title: template.title.clone(),
+            home_page_url: format!("{base_url}{}", template.alternate_path),
+            feed_url: format!("{base_url}{}", template.path),
+            authors: vec![JsonFeedAuthor { name: "Trinket Holloway" }],
+            language: "en-CA",
+            items: items,
+        };
+
+        Ok(FeedResponse::new(
+            serde_json::to_string(&feed).expect("Expected serializable feed"),
+            rocket::http::ContentType::new("application", "feed+json"),
+            template.last_modified,
+        ))
+    }
+
+    fn new(
+        body: String,
+        content_type: rocket::http::ContentType,
+        last_modified: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Self {
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = std::hash::DefaultHasher::new();
+        body.hash(&mut hasher);
+        let etag = format!("\"{:016x}\"", hasher.finish());
+
+        FeedResponse {
+            body: body,
+            content_type: content_type,
+            etag: etag,
+            last_modified: last_modified,
+        }
+    }
+
+    /// Check whether the client already has this version of the feed.
+    ///
+    /// If-None-Match wins if both headers are given, as per RFC 9110.
+    fn is_fresh(&self, request: &rocket::Request<'_>) -> bool {
+        let headers = request.headers();
+
+        if let Some(etags) = headers.get_one("If-None-Match") {
+            return etags
+                .split(',')
+                .map(|etag| etag.trim().trim_start_matches("W/"))
+                .any(|etag| etag == "*" || etag == self.etag);
+        }
+
+        let since = headers.get_one("If-Modified-Since").and_then(|since| {
+            chrono::DateTime::parse_from_rfc2822(since).ok()
+        });
+
+        match (since, self.last_modified) {
+            (Some(since), Some(last_modified)) => {
+                // HTTP dates only go down to the second
+                last_modified.timestamp() <= since.timestamp()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl<'r> rocket::response::Responder<'r, 'static> for FeedResponse {
+    fn respond_to(
+        self,
+        request: &'r rocket::Request<'_>,
+    ) -> rocket::response::Result<'static> {
+        let mut response = rocket::Response::build();
+        response.raw_header("ETag", self.etag.clone());
+        if let Some(last_modified) = self.last_modified {
+            response.raw_header(
+                "Last-Modified",
+                last_modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+            );
+        }
+
+        if self.is_fresh(request) {
+            response.status(rocket::http::Status::NotModified);
+        } else {
+            response
+                .header(self.content_type)
+                .sized_body(self.body.len(), std::io::Cursor::new(self.body));
+        }
+
+        response.ok()
+    }
 }
 
 /// The template for the `post` route.
@@ -73,9 +372,15 @@ struct PostTemplate {
     base_url: String,
     breadcrumbs: Vec<Breadcrumb>,
     post: Post,
-    files: Vec<PostImage>,
+    files: Vec<FileWithVariants>,
+    tags: Vec<Tag>,
     prev_post: Option<Post>,
     next_post: Option<Post>,
+    /// True if this is being shown at the post's preview URL
+    preview: bool,
+    /// What goes before the post's path in links to its files: the preview
+    /// URL on a preview page, and otherwise nothing
+    url_prefix: String,
 }
 
 /// The template for the `directory` route.
@@ -87,24 +392,258 @@ struct DirectoryTemplate {
     directory: Directory,
     posts: Vec<Post>,
     subdirs: Vec<Directory>,
+    page: u32,
+    prev_page: Option<PageLink>,
+    next_page: Option<PageLink>,
+}
+
+/// The template for the `archive`, `archive_year`, and `archive_month`
+/// routes.
+#[derive(askama::Template)]
+#[template(path = "archive.html")]
+struct ArchiveTemplate {
+    base_url: String,
+    /// The path of this page, for og:url
+    path: String,
+    breadcrumbs: Vec<Breadcrumb>,
+    title: String,
+    /// The years or months within this page that have posts, and how many
+    periods: Vec<(PageLink, usize)>,
+    posts: Vec<Post>,
+    prev_page: Option<PageLink>,
+    next_page: Option<PageLink>,
+}
+
+/// The template for the `search` route.
+#[derive(askama::Template)]
+#[template(path = "search.html")]
+struct SearchTemplate {
+    query: String,
+    results: Vec<SearchResult>,
+}
+
+/// A post that matched a search, with its best-matching text highlighted.
+struct SearchResult {
+    post: Post,
+    /// Already escaped, with the matching words in `<mark>`
+    snippet: String,
+}
+
+/// A row returned by the search query in the `search` route.
+#[derive(diesel::QueryableByName)]
+struct SearchMatch {
+    #[diesel(sql_type = diesel::sql_types::Integer)]
+    post_id: i32,
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    snippet: String,
+}
+
+/// The template for error pages, from the `error_page` catcher.
+#[derive(askama::Template)]
+#[template(path = "error.html")]
+struct ErrorTemplate {
+    status: rocket::http::Status,
+}
+
+/// The template for the `tag` route.
+#[derive(askama::Template)]
+#[template(path = "tag.html")]
+struct TagTemplate {
+    base_url: String,
+    tag: Tag,
+    posts: Vec<Post>,
 }
 
 /// A responder wrapping all the other responders the `path` route combines.
+// Only ever built to be returned straight away, so its size doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(rocket::Responder)]
 enum PathResponse {
     File(rocket::fs::NamedFile),
+    /// A post file, with the Content-Type recorded when it was uploaded
+    PostFile(rocket::fs::NamedFile, rocket::http::ContentType),
     Post(PostTemplate),
     Directory(DirectoryTemplate),
+    Feed(FeedResponse),
+    Redirect(rocket::response::Redirect),
 }
 
-/// Log an error and return an HTTP status.
+/// A lock for each thumbnail currently being generated, keyed by its path on
+/// disk.
+#[derive(Default)]
+struct ThumbnailLocks(
+    std::sync::Mutex<
+        std::collections::HashMap<
+            std::path::PathBuf,
+            std::sync::Arc<rocket::tokio::sync::Mutex<()>>,
+        >,
+    >,
+);
+
+impl ThumbnailLocks {
+    /// Wait for the lock for the given thumbnail path, creating it if
+    /// necessary.
+    async fn lock(&self, path: &std::path::Path) -> ThumbnailLock<'_> {
+        let lock = {
+            let mut locks = self.0.lock().expect("Expected unpoisoned lock");
+            locks.entry(path.to_path_buf()).or_default().clone()
+        };
+
+        ThumbnailLock {
+            locks: self,
+            path: path.to_path_buf(),
+            guard: lock.lock_owned().await,
+        }
+    }
+}
+
+/// A held lock from `ThumbnailLocks`, released when dropped.
+struct ThumbnailLock<'a> {
+    locks: &'a ThumbnailLocks,
+    path: std::path::PathBuf,
+    guard: rocket::tokio::sync::OwnedMutexGuard<()>,
+}
+
+impl Drop for ThumbnailLock<'_> {
+    /// Forget the lock once nobody else is waiting on it, however generating
+    /// the thumbnail went.
+    fn drop(&mut self) {
+        let mut locks = self.locks.0.lock().expect("Expected unpoisoned lock");
+        let lock = rocket::tokio::sync::OwnedMutexGuard::mutex(&self.guard);
+
+        // One handle is the map's, and the other is ours
+        if locks.get(&self.path).is_some_and(|entry| {
+            std::sync::Arc::ptr_eq(entry, lock)
+                && std::sync::Arc::strong_count(lock) == 2
+        }) {
+            locks.remove(&self.path);
+        }
+    }
+}
+
+/// Anything that can go wrong while serving a request.
 ///
-/// Returning a status lets all our routes return `Result<T, Status>` and then
-/// tidily deal with any other `Result` type with `result.map_err(log_error)?`
-/// if there's nothing better to be done with the error.
-fn log_error<T>(_: T) -> rocket::http::Status {
-    // TODO: actually log it
-    rocket::http::Status::InternalServerError
+/// All our routes return `Result<T, SiteError>`, so `?` works on whatever
+/// they run into.  The responder logs the underlying error along with the
+/// request it happened on, and then hands off to `error_page` with a status.
+#[derive(Debug)]
+enum SiteError {
+    Database(rocket_db_pools::diesel::result::Error),
+    Io(std::io::Error),
+    Thumbnail(cem::thumbnails::ThumbnailError),
+    Task(rocket::tokio::task::JoinError),
+    Template(askama::Error),
+    /// A status to respond with directly, for problems with the request itself
+    Status(rocket::http::Status),
+}
+
+impl SiteError {
+    /// The status to show the visitor.
+    ///
+    /// A file that's in the database but not on disk is still missing as far
+    /// as they're concerned, so that's a 404 rather than a 500.
+    fn status(&self) -> rocket::http::Status {
+        match self {
+            SiteError::Io(error)
+            | SiteError::Thumbnail(cem::thumbnails::ThumbnailError::Io(
+                error,
+            )) if error.kind() == std::io::ErrorKind::NotFound => {
+                rocket::http::Status::NotFound
+            }
+            SiteError::Status(status) => *status,
+            _ => rocket::http::Status::InternalServerError,
+        }
+    }
+}
+
+impl std::fmt::Display for SiteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SiteError::Database(error) => write!(f, "Database error: {error}"),
+            SiteError::Io(error) => write!(f, "I/O error: {error}"),
+            SiteError::Thumbnail(error) => {
+                write!(f, "Thumbnail error: {error}")
+            }
+            SiteError::Task(error) => write!(f, "Task error: {error}"),
+            SiteError::Template(error) => write!(f, "Template error: {error}"),
+            SiteError::Status(status) => write!(f, "{status}"),
+        }
+    }
+}
+
+impl From<rocket_db_pools::diesel::result::Error> for SiteError {
+    fn from(error: rocket_db_pools::diesel::result::Error) -> Self {
+        SiteError::Database(error)
+    }
+}
+
+impl From<std::io::Error> for SiteError {
+    fn from(error: std::io::Error) -> Self {
+        SiteError::Io(error)
+    }
+}
+
+impl From<cem::thumbnails::ThumbnailError> for SiteError {
+    fn from(error: cem::thumbnails::ThumbnailError) -> Self {
+        SiteError::Thumbnail(error)
+    }
+}
+
+impl From<rocket::tokio::task::JoinError> for SiteError {
+    fn from(error: rocket::tokio::task::JoinError) -> Self {
+        SiteError::Task(error)
+    }
+}
+
+impl From<askama::Error> for SiteError {
+    fn from(error: askama::Error) -> Self {
+        SiteError::Template(error)
+    }
+}
+
+impl<'r> rocket::response::Responder<'r, 'static> for SiteError {
+    fn respond_to(
+        self,
+        request: &'r rocket::Request<'_>,
+    ) -> rocket::response::Result<'static> {
+        let status = self.status();
+
+        match status.class() {
+            rocket::http::StatusClass::ServerError => rocket::error!(
+                "{} {}: {self}",
+                request.method(),
+                request.uri()
+            ),
+            // Not our fault, but if it's a missing file, it might be worth
+            // looking into
+            _ => {
+                rocket::warn!("{} {}: {self}", request.method(), request.uri())
+            }
+        }
+
+        Err(status)
+    }
+}
+
+/// Load the files for each of the given posts, along with their variants.
+async fn load_files(
+    db: &mut rocket_db_pools::Connection<cem::db::CEMDB>,
+    posts: &[Post],
+) -> Result<Vec<Vec<FileWithVariants>>, SiteError> {
+    let files = PostFile::belonging_to(posts)
+        .order(post_files::order)
+        .select(PostFile::as_select())
+        .load(db)
+        .await?;
+
+    let variants = PostFileVariant::belonging_to(&files)
+        .order(post_file_variants::width)
+        .select(PostFileVariant::as_select())
+        .load(db)
+        .await?
+        .grouped_by(&files);
+
+    Ok(files.into_iter().zip(variants).collect::<Vec<_>>().grouped_by(posts))
 }
 
 /// Serve the home page.
@@ -112,26 +651,21 @@ fn log_error<T>(_: T) -> rocket::http::Status {
 async fn index(
     mut db: rocket_db_pools::Connection<cem::db::CEMDB>,
     config: &rocket::State<cem::CEMConfig>,
-) -> Result<IndexTemplate, rocket::http::Status> {
+) -> Result<IndexTemplate, SiteError> {
     let posts = posts::table
         .inner_join(post_paths::table)
+        .filter(Post::listed())
         .order(posts::timestamp.desc())
         .limit(10)
         .select(Post::as_select())
         .load(&mut db)
-        .await
-        .map_err(log_error)?;
-
-    // Diesel will try and override posts.first() lol
-    let files = match posts.iter().next() {
-        Some(post) => PostImage::belonging_to(&post)
-            .order(post_images::order)
-            .select(PostImage::as_select())
-            .load(&mut db)
-            .await
-            .map_err(log_error)?,
-        None => vec![],
-    };
+        .await?;
+
+    // Only the very latest post shows its files
+    let files = load_files(&mut db, &posts[..posts.len().min(1)])
+        .await?
+        .pop()
+        .unwrap_or_default();
 
     Ok(IndexTemplate {
         base_url: config.base_url.clone(),
@@ -145,68 +679,335 @@ async fn index(
 async fn feed(
     mut db: rocket_db_pools::Connection<cem::db::CEMDB>,
     config: &rocket::State<cem::CEMConfig>,
-) -> Result<FeedResponse, rocket::http::Status> {
+) -> Result<FeedResponse, SiteError> {
+    let mut template = main_feed_template(&mut db, &config.base_url).await?;
+    template.path = "/feed.xml".to_string();
+
+    FeedResponse::atom(&template)
+}
+
+/// Serve the main feed in RSS 2.0, for readers that don't do Atom.
+#[rocket::get("/rss.xml")]
+async fn rss(
+    mut db: rocket_db_pools::Connection<cem::db::CEMDB>,
+    config: &rocket::State<cem::CEMConfig>,
+) -> Result<FeedResponse, SiteError> {
+    let mut template = main_feed_template(&mut db, &config.base_url).await?;
+    template.path = "/rss.xml".to_string();
+
+    FeedResponse::rss(&template)
+}
+
+/// Serve the main feed as a JSON Feed.
+#[rocket::get("/feed.json")]
+async fn json_feed(
+    mut db: rocket_db_pools::Connection<cem::db::CEMDB>,
+    config: &rocket::State<cem::CEMConfig>,
+) -> Result<FeedResponse, SiteError> {
+    let mut template = main_feed_template(&mut db, &config.base_url).await?;
+    template.path = "/feed.json".to_string();
+
+    FeedResponse::json(&template)
+}
+
+/// Build the main feed, which every format shares.
+///
+/// The path is left as the Atom feed's; change it for other formats.
+async fn main_feed_template(
+    db: &mut rocket_db_pools::Connection<cem::db::CEMDB>,
+    base_url: &str,
+) -> Result<FeedTemplate, SiteError> {
     let posts = posts::table
         .inner_join(post_paths::table)
         .filter(posts::timestamp.ge(SITE_LAUNCH))
-        .order(posts::timestamp)
+        .filter(Post::listed())
+        .order((posts::timestamp.desc(), posts::id.desc()))
+        .limit(FEED_LENGTH)
         .select(Post::as_select())
-        .load(&mut db)
-        .await
-        .map_err(log_error)?;
+        .load(db)
+        .await?;
+
+    let archive_count = feed_archive_count(db).await?;
+
+    let mut template = feed_template(
+        db,
+        posts,
+        base_url,
+        "feed".to_string(),
+        "Cat's Eye Marble".to_string(),
+        "/feed.xml".to_string(),
+        "/".to_string(),
+    )
+    .await?;
+    template.prev_archive_path =
+        (archive_count > 0).then(|| feed_archive_path(archive_count));
+
+    Ok(template)
+}
+
+/// Serve one of the main feed's archive documents (RFC 5005), which hold
+/// everything that's fallen off the end of the feed itself.
+///
+/// Each archive holds `FEED_LENGTH` posts, counting from the very first, so
+/// they don't change as new posts come in.  Posts only get archived once
+/// there's enough to fill one; until then, the newest are only in the feed.
+#[rocket::get("/feed/archive/<page>")]
+async fn feed_archive(
+    mut db: rocket_db_pools::Connection<cem::db::CEMDB>,
+    page: &str,
+    config: &rocket::State<cem::CEMConfig>,
+) -> Result<Option<FeedResponse>, SiteError> {
+    let Some(Ok(page)) = page.strip_suffix(".xml").map(str::parse::<i64>)
+    else {
+        return Ok(None);
+    };
+
+    let archive_count = feed_archive_count(&mut db).await?;
+    if page < 1 || page > archive_count {
+        return Ok(None);
+    }
 
-    let files = PostImage::belonging_to(&posts)
-        .order(post_images::order)
-        .select(PostImage::as_select())
+    let mut posts = posts::table
+        .inner_join(post_paths::table)
+        .filter(posts::timestamp.ge(SITE_LAUNCH))
+        .filter(Post::listed())
+        .order((posts::timestamp, posts::id))
+        .limit(FEED_LENGTH)
+        .offset((page - 1) * FEED_LENGTH)
+        .select(Post::as_select())
         .load(&mut db)
-        .await
-        .map_err(log_error)?
-        .grouped_by(&posts);
+        .await?;
+    posts.reverse();
+
+    let mut template = feed_template(
+        &mut db,
+        posts,
+        &config.base_url,
+        "feed".to_string(),
+        "Cat's Eye Marble".to_string(),
+        feed_archive_path(page),
+        "/".to_string(),
+    )
+    .await?;
+    template.archive = true;
+    template.prev_archive_path =
+        (page > 1).then(|| feed_archive_path(page - 1));
+    template.next_archive_path =
+        (page < archive_count).then(|| feed_archive_path(page + 1));
+
+    FeedResponse::atom(&template).map(Some)
+}
+
+/// Count the main feed's archive documents.
+async fn feed_archive_count(
+    db: &mut rocket_db_pools::Connection<cem::db::CEMDB>,
+) -> Result<i64, SiteError> {
+    let post_count: i64 = posts::table
+        .filter(posts::timestamp.ge(SITE_LAUNCH))
+        .filter(Post::listed())
+        .count()
+        .get_result(db)
+        .await?;
+
+    Ok(post_count / FEED_LENGTH)
+}
+
+/// The path of one of the main feed's archive documents.
+fn feed_archive_path(page: i64) -> String {
+    format!("/feed/archive/{page}.xml")
+}
 
-    let domain = rocket::http::uri::Absolute::parse(&config.base_url)
+/// Build a feed of the given posts.
+async fn feed_template(
+    db: &mut rocket_db_pools::Connection<cem::db::CEMDB>,
+    posts: Vec<Post>,
+    base_url: &str,
+    feed_id: String,
+    title: String,
+    path: String,
+    alternate_path: String,
+) -> Result<FeedTemplate, SiteError> {
+    let files = load_files(db, &posts).await?;
+    let updated = std::iter::zip(&posts, &files)
+        .map(|(post, files)| post_updated(post, files))
+        .max();
+    let last_modified = feeds_last_modified(db).await?;
+
+    let domain = rocket::http::uri::Absolute::parse(base_url)
         .expect("Expected valid base URL")
         .authority()
         .expect("Expected base URL authority")
         .host()
         .to_string();
 
-    Ok(FeedResponse {
-        template: FeedTemplate {
-            posts: posts,
-            files: files,
-            base_url: config.base_url.clone(),
-            domain: domain,
-        },
+    Ok(FeedTemplate {
+        posts: posts,
+        files: files,
+        base_url: base_url.to_string(),
+        domain: domain,
+        feed_id: feed_id,
+        title: title,
+        path: path,
+        alternate_path: alternate_path,
+        updated: updated,
+        last_modified: last_modified,
+        archive: false,
+        prev_archive_path: None,
+        next_archive_path: None,
     })
 }
 
+/// When anything that could be in a feed last changed, for feeds'
+/// Last-Modified headers.
+///
+/// This goes by every post rather than just the ones in the feed, so taking a
+/// post out of a feed (by unlisting it, say) still moves it forward, and so
+/// does a scheduled post going up.  (Scheduled posts are saved as last
+/// changed at their timestamp, so times after now are left out until then.)
+/// Deleting a post can still move it back, but that changes the ETag too,
+/// which is checked first.
+async fn feeds_last_modified(
+    db: &mut rocket_db_pools::Connection<cem::db::CEMDB>,
+) -> Result<Option<chrono::DateTime<chrono::Utc>>, SiteError> {
+    let posts_updated: Option<chrono::DateTime<chrono::Utc>> = posts::table
+        .filter(posts::updated_at.le(diesel::dsl::now))
+        .select(diesel::dsl::max(posts::updated_at))
+        .get_result(db)
+        .await?;
+    let files_updated: Option<chrono::DateTime<chrono::Utc>> =
+        post_files::table
+            .filter(post_files::updated_at.le(diesel::dsl::now))
+            .select(diesel::dsl::max(post_files::updated_at))
+            .get_result(db)
+            .await?;
+    let last_published: Option<chrono::DateTime<chrono::Utc>> = posts::table
+        .filter(Post::published())
+        .select(diesel::dsl::max(posts::timestamp))
+        .get_result(db)
+        .await?;
+
+    Ok([posts_updated, files_updated, last_published]
+        .into_iter()
+        .flatten()
+        .max())
+}
+
+/// Look up a tag by its slug, along with all the posts with that tag.
+async fn load_tag(
+    db: &mut rocket_db_pools::Connection<cem::db::CEMDB>,
+    slug: &str,
+) -> Result<Option<(Tag, Vec<Post>)>, SiteError> {
+    let result = tags::table
+        .filter(tags::slug.eq(slug))
+        .select(Tag::as_select())
+        .first(db)
+        .await
+        .optional()?;
+    let Some(tag) = result else { return Ok(None) };
+
+    let posts = PostTag::belonging_to(&tag)
+        .inner_join(posts::table.inner_join(post_paths::table))
+        .filter(Post::listed())
+        .order(posts::timestamp)
+        .select(Post::as_select())
+        .load(db)
+        .await?;
+
+    Ok(Some((tag, posts)))
+}
+
+/// Serve the page for a tag, listing every post with that tag.
+#[rocket::get("/tags/<slug>")]
+async fn tag(
+    mut db: rocket_db_pools::Connection<cem::db::CEMDB>,
+    slug: &str,
+    config: &rocket::State<cem::CEMConfig>,
+) -> Result<Option<TagTemplate>, SiteError> {
+    let Some((tag, posts)) = load_tag(&mut db, slug).await? else {
+        return Ok(None);
+    };
+
+    Ok(Some(TagTemplate {
+        base_url: config.base_url.clone(),
+        tag: tag,
+        posts: posts,
+    }))
+}
+
+/// Serve the Atom feed for a tag.
+#[rocket::get("/tags/<slug>/feed.xml")]
+async fn tag_feed(
+    mut db: rocket_db_pools::Connection<cem::db::CEMDB>,
+    slug: &str,
+    config: &rocket::State<cem::CEMConfig>,
+) -> Result<Option<FeedResponse>, SiteError> {
+    let Some((tag, posts)) = load_tag(&mut db, slug).await? else {
+        return Ok(None);
+    };
+
+    // Same cutoff and length as the main feed, newest first
+    let posts = posts
+        .into_iter()
+        .rev()
+        .filter(|post| post.timestamp >= SITE_LAUNCH)
+        .take(FEED_LENGTH as usize)
+        .collect();
+
+    let template = feed_template(
+        &mut db,
+        posts,
+        &config.base_url,
+        format!("tags/{}/feed", tag.slug),
+        format!("Cat's Eye Marble: {}", tag.name),
+        format!("/tags/{}/feed.xml", tag.slug),
+        format!("/tags/{}", tag.slug),
+    )
+    .await?;
+
+    FeedResponse::atom(&template).map(Some)
+}
+
 /// Respond to anything involving an arbitrary path.
 ///
 /// At the time of writing, Rocket only lets you have a multi-segment parameter
 /// at the end of the path.  TODO: look into request guards instead
-#[rocket::get("/<path..>?<height>", rank = 1000)]
+#[rocket::get("/<path..>?<height>&<page>", rank = 1000)]
 async fn path(
     mut db: rocket_db_pools::Connection<cem::db::CEMDB>,
     path: std::path::PathBuf,
     height: Option<i32>,
+    page: Option<u32>,
     config: &rocket::State<cem::CEMConfig>,
-) -> Result<Option<PathResponse>, rocket::http::Status> {
+    locks: &rocket::State<ThumbnailLocks>,
+) -> Result<Option<PathResponse>, SiteError> {
     // Tried to write this with .or_else but couldn't figure it out with async
-    if let Some(file) = file(&mut db, &path, &config.upload_dir).await? {
+    if let Some((file, content_type)) =
+        file(&mut db, &path, None, &config.upload_dir).await?
+    {
         // First because it might not even hit the db
-        Ok(Some(PathResponse::File(file)))
+        Ok(Some(PathResponse::PostFile(file, content_type)))
+    } else if let Some((file, content_type)) =
+        variant(&mut db, &path, None, &config.upload_dir).await?
+    {
+        Ok(Some(PathResponse::PostFile(file, content_type)))
     } else if let Some(thumbnail) =
-        thumbnail(&mut db, &path, height, &config.upload_dir).await?
+        thumbnail(&mut db, &path, None, height, config, locks).await?
     {
         Ok(Some(PathResponse::File(thumbnail)))
+    } else if let Some(feed) =
+        directory_feed(&mut db, &path, &config.base_url).await?
+    {
+        Ok(Some(PathResponse::Feed(feed)))
     } else if let Some(post) =
         post(&mut db, &path, config.base_url.clone()).await?
     {
         Ok(Some(PathResponse::Post(post)))
     } else if let Some(directory) =
-        directory(&mut db, &path, config.base_url.clone()).await?
+        directory(&mut db, &path, page, config.base_url.clone()).await?
     {
         Ok(Some(PathResponse::Directory(directory)))
+    } else if let Some(redirect) = redirect(&mut db, &path, height).await? {
+        Ok(Some(PathResponse::Redirect(redirect)))
     } else {
         Ok(None)
     }
@@ -216,7 +1017,7 @@ async fn path(
 ///
 /// e.g. `PathBuf::from("some/post/files/123")` -> `Some(("/some/post", 123))`
 /// (note that it adds the leading slash.)
-fn parse_file_path(path: &std::path::PathBuf) -> Option<(String, i32)> {
+fn parse_file_path(path: &std::path::Path) -> Option<(String, i32)> {
     let num = path.file_name()?.to_str()?.parse().ok()?;
 
     let path = path.parent()?;
@@ -230,67 +1031,203 @@ fn parse_file_path(path: &std::path::PathBuf) -> Option<(String, i32)> {
 }
 
 /// Serve a single file attached to a post.
+///
+/// Without a preview token, only published posts' files are served; with one,
+/// only the files of the post it belongs to.  This goes for variants and
+/// thumbnails too.
 async fn file(
     db: &mut rocket_db_pools::Connection<cem::db::CEMDB>,
-    path: &std::path::PathBuf,
-    upload_dir: &std::path::PathBuf,
-) -> Result<Option<rocket::fs::NamedFile>, rocket::http::Status> {
+    path: &std::path::Path,
+    preview_token: Option<&str>,
+    upload_dir: &std::path::Path,
+) -> Result<
+    Option<(rocket::fs::NamedFile, rocket::http::ContentType)>,
+    SiteError,
+> {
     let Some((path, num)) = parse_file_path(path) else { return Ok(None) };
 
-    let result = posts::table
-        .inner_join(post_images::table)
+    let query = posts::table
+        .inner_join(post_files::table)
         .inner_join(post_paths::table)
         .filter(post_paths::path.eq(path))
-        .filter(post_images::order.eq(num))
-        .select((PostImage::as_select(), Post::as_select()))
-        .first(db)
-        .await
-        .optional()
-        .map_err(log_error)?;
+        .filter(post_files::order.eq(num))
+        .select((PostFile::as_select(), Post::as_select()))
+        .into_boxed();
+    let query = match preview_token {
+        Some(token) => query.filter(posts::preview_token.eq(token)),
+        None => query.filter(Post::published()),
+    };
+
+    let result = query.first(db).await.optional()?;
     let Some((image, post)) = result else { return Ok(None) };
 
-    // Temporarily hardcoding this just to get this out the door
-    let suffix = match (post.slug.as_str(), image.order) {
-        ("a-bubble-blower-very-cool", 2) => "jpg",
-        _ => "png",
+    let local_path = upload_dir.join(format!(
+        "{}/files/{}.{}",
+        post.id, image.order, image.extension
+    ));
+    let file = rocket::fs::NamedFile::open(local_path).await?;
+    let content_type =
+        rocket::http::ContentType::parse_flexible(&image.media_type)
+            .unwrap_or(rocket::http::ContentType::Binary);
+
+    Ok(Some((file, content_type)))
+}
+
+/// Parse a URL path for a variant of a post file.
+///
+/// e.g. `PathBuf::from("some/post/files/1/640.webp")` ->
+/// `Some(("/some/post", 1, 640, "webp"))`
+fn parse_variant_path(
+    path: &std::path::Path,
+) -> Option<(String, i32, i32, String)> {
+    let width = path.file_stem()?.to_str()?.parse().ok()?;
+    let extension = path.extension()?.to_str()?.to_string();
+    let (path, order) = parse_file_path(path.parent()?)?;
+
+    Some((path, order, width, extension))
+}
+
+/// Serve a variant of a file attached to a post.
+async fn variant(
+    db: &mut rocket_db_pools::Connection<cem::db::CEMDB>,
+    path: &std::path::Path,
+    preview_token: Option<&str>,
+    upload_dir: &std::path::Path,
+) -> Result<
+    Option<(rocket::fs::NamedFile, rocket::http::ContentType)>,
+    SiteError,
+> {
+    let Some((path, order, width, extension)) = parse_variant_path(path)
+    else {
+        return Ok(None);
     };
 
-    let local_path = upload_dir
-        .join(format!("{}/files/{}.{}", post.id, image.order, suffix));
-    let file =
-        rocket::fs::NamedFile::open(local_path).await.map_err(log_error)?;
+    let query = post_file_variants::table
+        .inner_join(
+            post_files::table
+                .inner_join(posts::table.inner_join(post_paths::table)),
+        )
+        .filter(post_paths::path.eq(path))
+        .filter(post_files::order.eq(order))
+        .filter(post_file_variants::width.eq(width))
+        .filter(post_file_variants::extension.eq(extension))
+        .select((post_files::post_id, PostFileVariant::as_select()))
+        .into_boxed();
+    let query = match preview_token {
+        Some(token) => query.filter(posts::preview_token.eq(token)),
+        None => query.filter(Post::published()),
+    };
 
-    Ok(Some(file))
+    let result = query.first::<(i32, PostFileVariant)>(db).await.optional()?;
+    let Some((post_id, variant)) = result else { return Ok(None) };
+
+    let local_path = upload_dir.join(format!(
+        "{post_id}/variants/{}",
+        cem::variants::Variant::filename(
+            order,
+            width as u32,
+            &variant.extension
+        )
+    ));
+    let file = rocket::fs::NamedFile::open(local_path).await?;
+    let content_type =
+        rocket::http::ContentType::parse_flexible(&variant.media_type)
+            .unwrap_or(rocket::http::ContentType::Binary);
+
+    Ok(Some((file, content_type)))
 }
 
 /// Serve a thumbnail image for a post.
+///
+/// Only heights listed in `thumbnail_heights` are served.  Ones that haven't
+/// been generated yet are generated from the post's thumbnail source (see
+/// `Post::thumbnail_framing` for the part of it shown) and saved alongside
+/// the rest.
 async fn thumbnail(
     db: &mut rocket_db_pools::Connection<cem::db::CEMDB>,
-    path: &std::path::PathBuf,
+    path: &std::path::Path,
+    preview_token: Option<&str>,
     height: Option<i32>,
-    upload_dir: &std::path::PathBuf,
-) -> Result<Option<rocket::fs::NamedFile>, rocket::http::Status> {
+    config: &cem::CEMConfig,
+    locks: &ThumbnailLocks,
+) -> Result<Option<rocket::fs::NamedFile>, SiteError> {
     if path.file_name().and_then(|s| s.to_str()) != Some("thumbnail") {
         return Ok(None);
     }
     let Some(path) = path.parent() else { return Ok(None) };
     let path = format!("/{}", path.display());
 
-    let result = posts::table
+    let query = posts::table
         .inner_join(post_paths::table)
         .filter(post_paths::path.eq(path))
         .select(Post::as_select())
-        .first(db)
-        .await
-        .optional()
-        .map_err(log_error)?;
+        .into_boxed();
+    let query = match preview_token {
+        Some(token) => query.filter(posts::preview_token.eq(token)),
+        None => query.filter(Post::published()),
+    };
+
+    let result = query.first(db).await.optional()?;
     let Some(post) = result else { return Ok(None) };
 
     let height = height.unwrap_or(200);
-    let local_path =
-        upload_dir.join(format!("{}/thumbnails/{}.png", post.id, height));
-    let file =
-        rocket::fs::NamedFile::open(local_path).await.map_err(log_error)?;
+    if !config.thumbnail_heights.contains(&height) {
+        return Err(SiteError::Status(rocket::http::Status::BadRequest));
+    }
+
+    let thumbnails_dir =
+        config.upload_dir.join(format!("{}/thumbnails", post.id));
+    let local_path = thumbnails_dir.join(format!("{height}.png"));
+
+    if !local_path.exists() {
+        // Only one request gets to generate any given thumbnail; anyone else
+        // asking for it in the meantime waits and then serves the result
+        let _lock = locks.lock(&local_path).await;
+
+        if !local_path.exists() {
+            // Thumbnails come from a separate image, the chosen file, or the
+            // first image, in that order
+            let source_path = match &post.thumbnail_extension {
+                Some(extension) => config
+                    .upload_dir
+                    .join(format!("{}/thumbnail.{extension}", post.id)),
+                None => {
+                    let query = PostFile::belonging_to(&post)
+                        .order(post_files::order)
+                        .select(PostFile::as_select())
+                        .into_boxed();
+                    let query = match post.thumbnail_file_id {
+                        Some(id) => query.filter(post_files::id.eq(id)),
+                        None => query.filter(post_files::kind.eq("image")),
+                    };
+
+                    let result = query.first(db).await.optional()?;
+                    let Some(source) = result else { return Ok(None) };
+
+                    config.upload_dir.join(format!(
+                        "{}/files/{}.{}",
+                        post.id, source.order, source.extension
+                    ))
+                }
+            };
+            let framing = post.thumbnail_framing();
+            let dest = local_path.clone();
+
+            rocket::tokio::task::spawn_blocking(move || {
+                std::fs::create_dir_all(&thumbnails_dir)?;
+                let source =
+                    framing.apply(cem::thumbnails::open_image(&source_path)?);
+                cem::thumbnails::create_thumbnail(
+                    &source,
+                    height as u32,
+                    &dest,
+                )
+            })
+            .await??;
+        }
+    }
+
+    let file = rocket::fs::NamedFile::open(local_path).await?;
 
     Ok(Some(file))
 }
@@ -298,27 +1235,218 @@ async fn thumbnail(
 /// Serve the page for a post.
 async fn post(
     db: &mut rocket_db_pools::Connection<cem::db::CEMDB>,
-    path: &std::path::PathBuf,
+    path: &std::path::Path,
     base_url: String,
-) -> Result<Option<PostTemplate>, rocket::http::Status> {
+) -> Result<Option<PostTemplate>, SiteError> {
     let path = format!("/{}", path.display());
     let result = posts::table
         .inner_join(post_paths::table)
         .filter(post_paths::path.eq(path))
+        .filter(Post::published())
         .select(Post::as_select())
         .first(db)
         .await
-        .optional()
-        .map_err(log_error)?;
+        .optional()?;
 
     let Some(post) = result else { return Ok(None) };
 
-    let files = PostImage::belonging_to(&post)
-        .order(post_images::order)
-        .select(PostImage::as_select())
-        .load(db)
+    post_template(db, post, base_url, None).await.map(Some)
+}
+
+/// The most results shown for a search.
+const SEARCH_LIMIT: usize = 50;
+
+/// Where `ts_headline` starts and stops highlighting a match in a search
+/// snippet.  These get swapped for `<mark>` after the rest of the snippet has
+/// been escaped, so they need to be characters that won't be in any post.
+const HIGHLIGHT_START: char = '\u{2}';
+const HIGHLIGHT_STOP: char = '\u{3}';
+
+/// Search posts' titles, descriptions, and alt text.
+#[rocket::get("/search?<q>")]
+async fn search(
+    mut db: rocket_db_pools::Connection<cem::db::CEMDB>,
+    q: Option<String>,
+) -> Result<SearchTemplate, SiteError> {
+    let query = q.unwrap_or_default().trim().to_string();
+    if query.is_empty() {
+        return Ok(SearchTemplate { query: query, results: vec![] });
+    }
+
+    // Posts' own vectors and their files' vectors are matched separately so
+    // both indexes can be used, and then ranked together.  Only the listed
+    // posts (as in `Post::listed`) that make the cut get headlines, since
+    // those are slow.
+    let matches: Vec<SearchMatch> = diesel::sql_query(format!(
+        "with query as (
+            select websearch_to_tsquery('english', $1) as query
+        ),
+        matches as (
+            select posts.id as post_id,
+                ts_rank(posts.search_vector, query.query) as rank
+                from posts, query
+                where posts.search_vector @@ query.query
+            union all
+            select post_files.post_id,
+                ts_rank(post_files.search_vector, query.query)
+                from post_files, query
+                where post_files.search_vector @@ query.query
+        ),
+        results as (
+            select posts.id, sum(matches.rank) as rank, posts.timestamp
+                from matches
+                join posts on posts.id = matches.post_id
+                where not posts.draft
+                    and posts.timestamp <= now()
+                    and not posts.unlisted
+                group by posts.id
+                order by rank desc, posts.timestamp desc
+                limit {SEARCH_LIMIT}
+        )
+        select posts.id as post_id, ts_headline(
+            'english',
+            concat_ws(
+                ' ',
+                posts.description,
+                (
+                    select string_agg(alt_text, ' ' order by \"order\")
+                    from post_files
+                    where post_files.post_id = posts.id
+                )
+            ),
+            query.query,
+            'StartSel={HIGHLIGHT_START}, StopSel={HIGHLIGHT_STOP}, \
+             MaxWords=35, MinWords=15, MaxFragments=2, \
+             FragmentDelimiter=\" … \"'
+        ) as snippet
+        from results
+        join posts on posts.id = results.id
+        cross join query
+        order by results.rank desc, results.timestamp desc"
+    ))
+    .bind::<diesel::sql_types::Text, _>(&query)
+    .load(&mut db)
+    .await?;
+
+    let ids: Vec<i32> = matches.iter().map(|result| result.post_id).collect();
+    let mut posts: std::collections::HashMap<i32, Post> = posts::table
+        .inner_join(post_paths::table)
+        .filter(posts::id.eq_any(ids))
+        .select(Post::as_select())
+        .load(&mut db)
+        .await?
+        .into_iter()
+        .map(|post| (post.id, post))
+        .collect();
+
+    let results = matches
+        .into_iter()
+        .filter_map(|result| {
+            Some(SearchResult {
+                post: posts.remove(&result.post_id)?,
+                snippet: highlight(&result.snippet),
+            })
+        })
+        .collect();
+
+    Ok(SearchTemplate { query: query, results: results })
+}
+
+/// Escape a search snippet for HTML, and turn its highlight markers into
+/// `<mark>` tags.
+fn highlight(snippet: &str) -> String {
+    let mut html = String::with_capacity(snippet.len());
+
+    for c in snippet.chars() {
+        match c {
+            HIGHLIGHT_START => html.push_str("<mark>"),
+            HIGHLIGHT_STOP => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#x27;"),
+            c => html.push(c),
+        }
+    }
+
+    html
+}
+
+/// Serve the page for a post, whether or not it's been published yet, at a
+/// URL that's only known to whoever has access to the CLI.
+///
+/// The post's files and thumbnails are served underneath it by `preview_file`,
+/// since its usual path won't serve them until it's published.
+#[rocket::get("/preview/<token>")]
+async fn preview(
+    mut db: rocket_db_pools::Connection<cem::db::CEMDB>,
+    token: &str,
+    config: &rocket::State<cem::CEMConfig>,
+) -> Result<Option<PostTemplate>, SiteError> {
+    let result = posts::table
+        .inner_join(post_paths::table)
+        .filter(posts::preview_token.eq(token))
+        .select(Post::as_select())
+        .first(&mut db)
         .await
-        .map_err(log_error)?;
+        .optional()?;
+
+    let Some(post) = result else { return Ok(None) };
+
+    post_template(&mut db, post, config.base_url.clone(), Some(token))
+        .await
+        .map(Some)
+}
+
+/// Serve a file, variant, or thumbnail for a post's preview page, at its usual
+/// path under the preview URL.
+#[rocket::get("/preview/<token>/<path..>?<height>", rank = 2)]
+async fn preview_file(
+    mut db: rocket_db_pools::Connection<cem::db::CEMDB>,
+    token: &str,
+    path: std::path::PathBuf,
+    height: Option<i32>,
+    config: &rocket::State<cem::CEMConfig>,
+    locks: &rocket::State<ThumbnailLocks>,
+) -> Result<Option<PathResponse>, SiteError> {
+    let token = Some(token);
+
+    if let Some((file, content_type)) =
+        file(&mut db, &path, token, &config.upload_dir).await?
+    {
+        Ok(Some(PathResponse::PostFile(file, content_type)))
+    } else if let Some((file, content_type)) =
+        variant(&mut db, &path, token, &config.upload_dir).await?
+    {
+        Ok(Some(PathResponse::PostFile(file, content_type)))
+    } else if let Some(thumbnail) =
+        thumbnail(&mut db, &path, token, height, config, locks).await?
+    {
+        Ok(Some(PathResponse::File(thumbnail)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Load everything else the page for a post needs.
+async fn post_template(
+    db: &mut rocket_db_pools::Connection<cem::db::CEMDB>,
+    post: Post,
+    base_url: String,
+    preview_token: Option<&str>,
+) -> Result<PostTemplate, SiteError> {
+    let files = load_files(db, std::slice::from_ref(&post))
+        .await?
+        .pop()
+        .unwrap_or_default();
+
+    let tags = PostTag::belonging_to(&post)
+        .inner_join(tags::table)
+        .order(tags::name)
+        .select(Tag::as_select())
+        .load(db)
+        .await?;
 
     let mut parent_id = Some(post.directory_id);
     let mut breadcrumbs = Vec::new();
@@ -329,8 +1457,7 @@ async fn post(
             .inner_join(directory_paths::table)
             .select(Directory::as_select())
             .first(db)
-            .await
-            .map_err(log_error)?;
+            .await?;
 
         breadcrumbs
             .push(Breadcrumb { path: directory.path, label: directory.title });
@@ -343,39 +1470,53 @@ async fn post(
     let next_post = posts::table
         .inner_join(post_paths::table)
         .filter(posts::timestamp.gt(post.timestamp))
+        .filter(Post::listed())
         .order(posts::timestamp.asc())
         .select(Post::as_select())
         .first(db)
         .await
-        .optional()
-        .map_err(log_error)?;
+        .optional()?;
 
     let prev_post = posts::table
         .inner_join(post_paths::table)
         .filter(posts::timestamp.lt(post.timestamp))
+        .filter(Post::listed())
         .order(posts::timestamp.desc())
         .select(Post::as_select())
         .first(db)
         .await
-        .optional()
-        .map_err(log_error)?;
+        .optional()?;
 
-    Ok(Some(PostTemplate {
+    Ok(PostTemplate {
         base_url: base_url,
         breadcrumbs: breadcrumbs,
         post: post,
         files: files,
+        tags: tags,
         prev_post: prev_post,
         next_post: next_post,
-    }))
+        preview: preview_token.is_some(),
+        url_prefix: preview_token
+            .map(|token| format!("/preview/{token}"))
+            .unwrap_or_default(),
+    })
 }
 
-/// Serve the page for a directory, listing posts and subdirectories.
+/// How many posts are shown on each page of a directory.
+const DIRECTORY_PAGE_SIZE: u32 = 48;
+
+/// Serve a page of a directory, listing posts and subdirectories.
 async fn directory(
     db: &mut rocket_db_pools::Connection<cem::db::CEMDB>,
-    path: &std::path::PathBuf,
+    path: &std::path::Path,
+    page: Option<u32>,
     base_url: String,
-) -> Result<Option<DirectoryTemplate>, rocket::http::Status> {
+) -> Result<Option<DirectoryTemplate>, SiteError> {
+    let page = page.unwrap_or(1);
+    if page == 0 {
+        return Ok(None);
+    }
+
     let path = format!("/{}", path.display());
     let result = directories::table
         .inner_join(directory_paths::table)
@@ -383,8 +1524,7 @@ async fn directory(
         .select(Directory::as_select())
         .first(db)
         .await
-        .optional()
-        .map_err(log_error)?;
+        .optional()?;
 
     let Some(directory) = result else { return Ok(None) };
 
@@ -397,8 +1537,7 @@ async fn directory(
             .inner_join(directory_paths::table)
             .select(Directory::as_select())
             .first(db)
-            .await
-            .map_err(log_error)?;
+            .await?;
 
         breadcrumbs
             .push(Breadcrumb { path: directory.path, label: directory.title });
@@ -408,13 +1547,39 @@ async fn directory(
 
     let breadcrumbs = breadcrumbs.into_iter().rev().collect();
 
-    let posts = Post::belonging_to(&directory)
+    // One extra, to tell whether there's a next page
+    // Pages too far along to count can't have anything on them
+    let Some(offset) = (page - 1).checked_mul(DIRECTORY_PAGE_SIZE) else {
+        return Ok(None);
+    };
+
+    let mut posts = Post::belonging_to(&directory)
         .inner_join(post_paths::table)
-        .order(posts::timestamp)
+        .filter(Post::listed())
+        .order((posts::timestamp, posts::id))
+        .limit((DIRECTORY_PAGE_SIZE + 1).into())
+        .offset(offset.into())
         .select(Post::as_select())
         .load(db)
-        .await
-        .map_err(log_error)?;
+        .await?;
+
+    if page > 1 && posts.is_empty() {
+        return Ok(None);
+    }
+
+    let next_page = (posts.len() > DIRECTORY_PAGE_SIZE as usize).then(|| {
+        posts.truncate(DIRECTORY_PAGE_SIZE as usize);
+        PageLink {
+            path: format!("{}?page={}", directory.path, page + 1),
+            label: "Next page".to_string(),
+        }
+    });
+    let prev_page = match page {
+        1 => None,
+        2 => Some(directory.path.clone()),
+        _ => Some(format!("{}?page={}", directory.path, page - 1)),
+    }
+    .map(|path| PageLink { path: path, label: "Previous page".to_string() });
 
     let subdirs = directories::table
         .inner_join(directory_paths::table)
@@ -422,8 +1587,7 @@ async fn directory(
         .order(directories::title)
         .select(Directory::as_select())
         .load(db)
-        .await
-        .map_err(log_error)?;
+        .await?;
 
     Ok(Some(DirectoryTemplate {
         base_url: base_url,
@@ -431,15 +1595,341 @@ async fn directory(
         directory: directory,
         posts: posts,
         subdirs: subdirs,
+        page: page,
+        prev_page: prev_page,
+        next_page: next_page,
     }))
 }
 
+/// Serve the Atom feed for a directory, covering every post in it and in all
+/// its subdirectories.
+async fn directory_feed(
+    db: &mut rocket_db_pools::Connection<cem::db::CEMDB>,
+    path: &std::path::Path,
+    base_url: &str,
+) -> Result<Option<FeedResponse>, SiteError> {
+    if path.file_name().and_then(|s| s.to_str()) != Some("feed.xml") {
+        return Ok(None);
+    }
+    let Some(path) = path.parent() else { return Ok(None) };
+    let path = format!("/{}", path.display());
+
+    let result = directories::table
+        .inner_join(directory_paths::table)
+        .filter(directory_paths::path.eq(&path))
+        .select(Directory::as_select())
+        .first(db)
+        .await
+        .optional()?;
+    let Some(directory) = result else { return Ok(None) };
+
+    // directory_subtrees does the recursive walk for us
+    let subtree = directory_subtrees::table
+        .filter(directory_subtrees::directory_id.eq(directory.id))
+        .select(directory_subtrees::descendant_id);
+
+    let posts = posts::table
+        .inner_join(post_paths::table)
+        .filter(posts::directory_id.eq_any(subtree))
+        .filter(posts::timestamp.ge(SITE_LAUNCH))
+        .filter(Post::listed())
+        .order((posts::timestamp.desc(), posts::id.desc()))
+        .limit(FEED_LENGTH)
+        .select(Post::as_select())
+        .load(db)
+        .await?;
+
+    let template = feed_template(
+        db,
+        posts,
+        base_url,
+        format!("{}/feed", path.trim_start_matches('/')),
+        format!("Cat's Eye Marble: {}", directory.title),
+        format!("{path}/feed.xml"),
+        path,
+    )
+    .await?;
+
+    FeedResponse::atom(&template).map(Some)
+}
+
+/// Count the listed posts made in each month, by year and month in the
+/// display time zone.
+///
+/// There aren't so many posts that it's worth grouping them in the database.
+async fn archive_counts(
+    db: &mut rocket_db_pools::Connection<cem::db::CEMDB>,
+) -> Result<std::collections::BTreeMap<(i32, u32), usize>, SiteError> {
+    use chrono::Datelike;
+
+    let timestamps: Vec<chrono::DateTime<chrono::Utc>> = posts::table
+        .filter(Post::listed())
+        .select(posts::timestamp)
+        .load(db)
+        .await?;
+
+    let mut counts = std::collections::BTreeMap::new();
+    for timestamp in timestamps {
+        let local = cem::local_time(&timestamp);
+        *counts.entry((local.year(), local.month())).or_default() += 1;
+    }
+
+    Ok(counts)
+}
+
+/// A link to the archive page for a year.
+fn year_link(year: i32) -> PageLink {
+    PageLink { path: format!("/archive/{year}"), label: year.to_string() }
+}
+
+/// A link to the archive page for a month, labelled with its name and
+/// optionally the year.
+fn month_link(year: i32, month: u32, with_year: bool) -> PageLink {
+    let format = if with_year { "%B %Y" } else { "%B" };
+    let label = chrono::NaiveDate::from_ymd_opt(year, month, 1)
+        .map(|date| date.format(format).to_string())
+        .unwrap_or_default();
+
+    PageLink { path: format!("/archive/{year}/{month:02}"), label: label }
+}
+
+/// List every year that has posts.
+#[rocket::get("/archive")]
+async fn archive(
+    mut db: rocket_db_pools::Connection<cem::db::CEMDB>,
+    config: &rocket::State<cem::CEMConfig>,
+) -> Result<ArchiveTemplate, SiteError> {
+    let mut years: Vec<(i32, usize)> = Vec::new();
+
+    // Newest first, same as the home page
+    for ((year, _), count) in archive_counts(&mut db).await?.into_iter().rev()
+    {
+        match years.last_mut() {
+            Some((last_year, total)) if *last_year == year => *total += count,
+            _ => years.push((year, count)),
+        }
+    }
+
+    Ok(ArchiveTemplate {
+        base_url: config.base_url.clone(),
+        path: "/archive".to_string(),
+        breadcrumbs: vec![],
+        title: "Archive".to_string(),
+        periods: years
+            .into_iter()
+            .map(|(year, count)| (year_link(year), count))
+            .collect(),
+        posts: vec![],
+        prev_page: None,
+        next_page: None,
+    })
+}
+
+/// List every month in a year that has posts.
+#[rocket::get("/archive/<year>")]
+async fn archive_year(
+    mut db: rocket_db_pools::Connection<cem::db::CEMDB>,
+    year: i32,
+    config: &rocket::State<cem::CEMConfig>,
+) -> Result<Option<ArchiveTemplate>, SiteError> {
+    let counts = archive_counts(&mut db).await?;
+
+    let months: Vec<(PageLink, usize)> = counts
+        .range((year, 1)..=(year, 12))
+        .rev()
+        .map(|(&(year, month), &count)| {
+            (month_link(year, month, false), count)
+        })
+        .collect();
+
+    if months.is_empty() {
+        return Ok(None);
+    }
+
+    let prev_year = counts.range(..(year, 1)).next_back();
+    let next_year =
+        year.checked_add(1).and_then(|next| counts.range((next, 1)..).next());
+
+    Ok(Some(ArchiveTemplate {
+        base_url: config.base_url.clone(),
+        path: format!("/archive/{year}"),
+        breadcrumbs: vec![Breadcrumb {
+            path: "/archive".to_string(),
+            label: "Archive".to_string(),
+        }],
+        title: year.to_string(),
+        periods: months,
+        posts: vec![],
+        prev_page: prev_year.map(|(&(year, _), _)| year_link(year)),
+        next_page: next_year.map(|(&(year, _), _)| year_link(year)),
+    }))
+}
+
+/// List the posts made in a month.
+#[rocket::get("/archive/<year>/<month>")]
+async fn archive_month(
+    mut db: rocket_db_pools::Connection<cem::db::CEMDB>,
+    year: i32,
+    month: u32,
+    config: &rocket::State<cem::CEMConfig>,
+) -> Result<Option<ArchiveTemplate>, SiteError> {
+    let Some(start) = chrono::NaiveDate::from_ymd_opt(year, month, 1) else {
+        return Ok(None);
+    };
+    // The last month chrono can represent has no end, so no archive either
+    let Some(end) = start.checked_add_months(chrono::Months::new(1)) else {
+        return Ok(None);
+    };
+
+    // Checked first since Postgres can't compare against every date chrono
+    // can represent
+    let counts = archive_counts(&mut db).await?;
+    if !counts.contains_key(&(year, month)) {
+        return Ok(None);
+    }
+
+    // Months start at local midnight, or just after if that's skipped for
+    // daylight saving time
+    let local_midnight = |date: chrono::NaiveDate| {
+        let midnight = date.and_time(chrono::NaiveTime::MIN);
+        let timezone = cem::display_timezone();
+        (0..3)
+            .find_map(|hours| {
+                let time = midnight
+                    .checked_add_signed(chrono::TimeDelta::hours(hours))?;
+                time.and_local_timezone(timezone).earliest()
+            })
+            .map(|time| time.to_utc())
+    };
+    let (Some(start), Some(end)) =
+        (local_midnight(start), local_midnight(end))
+    else {
+        return Ok(None);
+    };
+
+    let posts = posts::table
+        .inner_join(post_paths::table)
+        .filter(Post::listed())
+        .filter(posts::timestamp.ge(start))
+        .filter(posts::timestamp.lt(end))
+        .order((posts::timestamp, posts::id))
+        .select(Post::as_select())
+        .load(&mut db)
+        .await?;
+
+    let prev_month = counts.range(..(year, month)).next_back();
+    let next_month = counts.range((year, month + 1)..).next();
+
+    Ok(Some(ArchiveTemplate {
+        base_url: config.base_url.clone(),
+        path: format!("/archive/{year}/{month:02}"),
+        breadcrumbs: vec![
+            Breadcrumb {
+                path: "/archive".to_string(),
+                label: "Archive".to_string(),
+            },
+            Breadcrumb {
+                path: format!("/archive/{year}"),
+                label: year.to_string(),
+            },
+        ],
+        title: month_link(year, month, true).label,
+        periods: vec![],
+        posts: posts,
+        prev_page: prev_month
+            .map(|(&(year, month), _)| month_link(year, month, true)),
+        next_page: next_month
+            .map(|(&(year, month), _)| month_link(year, month, true)),
+    }))
+}
+
+/// Look for the new location of a post or directory that used to live at the
+/// given path.
+///
+/// The path can also be one of an old post's files or its thumbnail, in which
+/// case the redirect is to the same file under the post's new path.
+async fn redirect(
+    db: &mut rocket_db_pools::Connection<cem::db::CEMDB>,
+    path: &std::path::Path,
+    height: Option<i32>,
+) -> Result<Option<rocket::response::Redirect>, SiteError> {
+    let candidates: Vec<String> = path
+        .ancestors()
+        .filter(|ancestor| !ancestor.as_os_str().is_empty())
+        .map(|ancestor| format!("/{}", ancestor.display()))
+        .collect();
+
+    // Most specific first, in case a moved post's old directory was moved too
+    let result = redirects::table
+        .left_join(
+            post_paths::table
+                .on(post_paths::post_id.nullable().eq(redirects::post_id)),
+        )
+        .left_join(
+            directory_paths::table.on(directory_paths::directory_id
+                .nullable()
+                .eq(redirects::directory_id)),
+        )
+        .filter(redirects::path.eq_any(&candidates))
+        .select((
+            redirects::path,
+            post_paths::path.nullable(),
+            directory_paths::path.nullable(),
+        ))
+        .load::<(String, Option<String>, Option<String>)>(db)
+        .await?
+        .into_iter()
+        .max_by_key(|(old_path, _, _)| old_path.len());
+
+    let location = match result {
+        Some((old_path, Some(new_path), _)) => {
+            // Only a post's files and thumbnail live underneath it
+            let rest = &candidates[0][old_path.len()..];
+            if !(rest.is_empty()
+                || rest.starts_with("/files/")
+                || rest == "/thumbnail")
+            {
+                return Ok(None);
+            }
+
+            match height {
+                Some(height) if rest == "/thumbnail" => {
+                    format!("{new_path}{rest}?height={height}")
+                }
+                _ => format!("{new_path}{rest}"),
+            }
+        }
+        Some((old_path, None, Some(new_path))) => {
+            // A directory's own feed is the only thing that can come along
+            // with it; anything inside has its own redirect
+            match &candidates[0][old_path.len()..] {
+                "" => new_path,
+                "/feed.xml" => format!("{new_path}/feed.xml"),
+                _ => return Ok(None),
+            }
+        }
+        _ => return Ok(None),
+    };
+
+    Ok(Some(rocket::response::Redirect::moved(location)))
+}
+
+/// Show a page for any error status, including ones returned by `SiteError`.
+#[rocket::catch(default)]
+fn error_page(
+    status: rocket::http::Status,
+    _request: &rocket::Request,
+) -> ErrorTemplate {
+    ErrorTemplate { status: status }
+}
+
 /// Launch Rocket.
 #[rocket::launch]
 fn rocket() -> _ {
     let rocket = rocket::build();
     let config: cem::CEMConfig =
         rocket.figment().extract_inner("cem").expect("Expected valid config");
+    config.set_display_timezone();
 
     // Putting the cachebust in the /static/ path rather than the query means
     // CSS can use relative URLs for background images etc. and avoid needing
@@ -451,7 +1941,27 @@ fn rocket() -> _ {
     rocket
         .attach(cem::db::CEMDB::init())
         .manage(config)
-        .mount("/", rocket::routes![index, feed, path])
+        .manage(ThumbnailLocks::default())
+        .mount(
+            "/",
+            rocket::routes![
+                index,
+                feed,
+                rss,
+                json_feed,
+                feed_archive,
+                tag,
+                tag_feed,
+                search,
+                archive,
+                archive_year,
+                archive_month,
+                preview,
+                preview_file,
+                path,
+            ],
+        )
+        .register("/", rocket::catchers![error_page])
         .mount(
             format!("/static/{}", *CACHEBUST),
             rocket::fs::FileServer::from("static"),
@@ -0,0 +1,129 @@
+//! Responsive variants of full-size post images.
+//!
+//! Originals can be several megabytes, so alongside each one we save a few
+//! smaller copies in modern formats for browsers to pick from with `srcset`.
+
+use std::path::Path;
+
+use crate::thumbnails::ThumbnailError;
+
+/// The widths variants are generated at, for originals wider than that.
+pub const VARIANT_WIDTHS: [u32; 3] = [640, 1280, 1920];
+
+/// The formats variants are generated in, in the order browsers should
+/// prefer them.
+pub const VARIANT_FORMATS: [VariantFormat; 2] =
+    [VariantFormat::Avif, VariantFormat::WebP];
+
+/// Original formats that don't get variants: GIFs would lose their
+/// animation, and AVIFs can't be decoded without the system dav1d library
+/// (and are already about as small as they're going to get).
+pub const SKIPPED_MEDIA_TYPES: [&str; 2] = ["image/gif", "image/avif"];
+
+/// A modern image format variants can be saved in.
+#[derive(Clone, Copy)]
+pub enum VariantFormat {
+    Avif,
+    WebP,
+}
+
+impl VariantFormat {
+    pub fn media_type(self) -> &'static str {
+        match self {
+            VariantFormat::Avif => "image/avif",
+            VariantFormat::WebP => "image/webp",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            VariantFormat::Avif => "avif",
+            VariantFormat::WebP => "webp",
+        }
+    }
+
+    /// Encode an image in this format.
+    fn encode(
+        self,
+        image: &image::RgbaImage,
+    ) -> Result<Vec<u8>, ThumbnailError> {
+        match self {
+            VariantFormat::Avif => {
+                let mut avif = Vec::new();
+                image.write_with_encoder(
+                    image::codecs::avif::AvifEncoder::new_with_speed_quality(
+                        &mut avif, 6, 75,
+                    ),
+                )?;
+                Ok(avif)
+            }
+            VariantFormat::WebP => {
+                let webp = webp::Encoder::from_rgba(
+                    image.as_raw(),
+                    image.width(),
+                    image.height(),
+                )
+                .encode_simple(false, 85.0)
+                .map_err(|error| ThumbnailError::WebP(format!("{error:?}")))?;
+                Ok(webp.to_vec())
+            }
+        }
+    }
+}
+
+/// A variant that was saved to disk.
+pub struct Variant {
+    pub format: VariantFormat,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Variant {
+    /// The variant's filename within the post's `variants` directory.
+    pub fn filename(order: i32, width: u32, extension: &str) -> String {
+        format!("{order}-{width}.{extension}")
+    }
+}
+
+/// Generate variants of the given post file and save them in `variants_dir`,
+/// returning what was saved.
+///
+/// Nothing is generated for images that are already narrower than the
+/// smallest variant width, or for formats that shouldn't be re-encoded.
+pub fn create_variants(
+    variants_dir: &Path,
+    image_path: &Path,
+    media_type: &str,
+    order: i32,
+) -> Result<Vec<Variant>, ThumbnailError> {
+    if SKIPPED_MEDIA_TYPES.contains(&media_type) {
+        return Ok(vec![]);
+    }
+
+    let source = crate::thumbnails::open_image(image_path)?;
+    let mut variants = Vec::new();
+
+    for width in VARIANT_WIDTHS {
+        if width >= source.width() {
+            break;
+        }
+
+        let scaled = crate::thumbnails::scale_to_width(&source, width);
+
+        for format in VARIANT_FORMATS {
+            let filename = Variant::filename(order, width, format.extension());
+            std::fs::write(
+                variants_dir.join(filename),
+                format.encode(&scaled)?,
+            )?;
+
+            variants.push(Variant {
+                format: format,
+                width: scaled.width(),
+                height: scaled.height(),
+            });
+        }
+    }
+
+    Ok(variants)
+}
@@ -0,0 +1,15 @@
+#!/bin/sh
+# Make the reference thumbnails for thumbnails::tests::matches_old_pipeline
+# with the pipeline they're meant to match, the one the CLI used to shell out
+# to.  These are the source of truth, not make_fixtures.py's stand-ins.  Needs
+# ImageMagick and optipng.
+set -e
+cd "$(dirname "$0")"
+
+for spec in gradient:16 gradient:10 alpha:15; do
+    name=${spec%:*}
+    height=${spec#*:}
+    convert "$name.png" -gamma 0.456 -filter box -scale "x$height" \
+        -gamma 2.2 "$name-$height.png"
+    optipng -quiet -strip all "$name-$height.png"
+done
@@ -0,0 +1,174 @@
+#!/usr/bin/env python3
+"""Make the thumbnail test's source images.
+
+The reference thumbnails they're compared against are made from these by
+`make-references.sh`, which runs the old `convert`/`optipng` pipeline itself.
+That's the source of truth; run it after changing anything here.
+
+`--stand-in-references` also writes references computed by porting that
+pipeline's arithmetic (16-bit ImageMagick: `-gamma 0.456`, `-scale
+x<height>`, `-gamma 2.2`, then saved at 8 bits), for when ImageMagick isn't
+around.  They only show the Rust code agrees with this port, not with
+ImageMagick, so don't commit them over real ones.
+
+`gradient.avif` is `gradient.png` saved as AVIF by any encoder; it's only used
+to check that AVIF is taken as an image but not made into thumbnails.
+"""
+
+import struct
+import sys
+import zlib
+from pathlib import Path
+
+HERE = Path(__file__).parent
+QUANTUM = 65535
+
+
+def chunk(kind, data):
+    return (
+        struct.pack(">I", len(data))
+        + kind
+        + data
+        + struct.pack(">I", zlib.crc32(kind + data))
+    )
+
+
+def write_png(path, width, height, pixels, extra_chunks=()):
+    """Write 8-bit RGBA pixels (a list of rows of (r, g, b, a)) as a PNG."""
+    raw = b"".join(
+        b"\0" + bytes(channel for pixel in row for channel in pixel)
+        for row in pixels
+    )
+    png = b"\x89PNG\r\n\x1a\n"
+    png += chunk(b"IHDR", struct.pack(">IIBBBBB", width, height, 8, 6, 0, 0, 0))
+    for kind, data in extra_chunks:
+        png += chunk(kind, data)
+    png += chunk(b"IDAT", zlib.compress(raw, 9))
+    png += chunk(b"IEND", b"")
+    path.write_bytes(png)
+
+
+def gamma(value, exponent):
+    """ImageMagick's -gamma on one 16-bit channel value."""
+    return round(QUANTUM * (value / QUANTUM) ** (1 / exponent))
+
+
+def scale(pixels, width, height, out_width, out_height):
+    """ImageMagick's -scale: area averaging, weighted by alpha."""
+
+    def weights(in_len, out_len):
+        ratio = in_len / out_len
+        result = []
+        for out in range(out_len):
+            start, end = out * ratio, (out + 1) * ratio
+            result.append(
+                [
+                    (i, (min(end, i + 1) - max(start, i)) / ratio)
+                    for i in range(int(start), min(int(-(-end // 1)), in_len))
+                    if min(end, i + 1) > max(start, i)
+                ]
+            )
+        return result
+
+    xs, ys = weights(width, out_width), weights(height, out_height)
+    out = []
+    for y_weights in ys:
+        row = []
+        for x_weights in xs:
+            sums = [0.0] * 4
+            for y, wy in y_weights:
+                for x, wx in x_weights:
+                    r, g, b, a = pixels[y][x]
+                    weight = wx * wy
+                    alpha = a / QUANTUM
+                    sums[0] += r * alpha * weight
+                    sums[1] += g * alpha * weight
+                    sums[2] += b * alpha * weight
+                    sums[3] += a * weight
+            alpha = sums[3] / QUANTUM
+            reciprocal = 1 / alpha if alpha > 0 else 0
+            row.append(
+                tuple(
+                    min(QUANTUM, max(0, round(value * reciprocal)))
+                    for value in sums[:3]
+                )
+                + (min(QUANTUM, max(0, round(sums[3]))),)
+            )
+        out.append(row)
+    return out
+
+
+def reference(pixels, height):
+    """The old thumbnail pipeline, at the given height."""
+    in_height, in_width = len(pixels), len(pixels[0])
+    width = max(1, int(in_width * height / in_height + 0.5))
+
+    quantum = [
+        [
+            tuple(gamma(c * 257, 0.456) for c in pixel[:3]) + (pixel[3] * 257,)
+            for pixel in row
+        ]
+        for row in pixels
+    ]
+    scaled = scale(quantum, in_width, in_height, width, height)
+    eight_bit = [
+        [
+            tuple((gamma(c, 2.2) + 128) // 257 for c in pixel[:3])
+            + ((pixel[3] + 128) // 257,)
+            for pixel in row
+        ]
+        for row in scaled
+    ]
+    return width, eight_bit
+
+
+def main():
+    gradient = [
+        [
+            (x * 255 // 47, y * 255 // 31, 230 if (x // 3 + y // 3) % 2 else 30, 255)
+            for x in range(48)
+        ]
+        for y in range(32)
+    ]
+    alpha = [
+        [
+            (200, 40 + x * 5, 255 - y * 6, 0 if x < 4 else min(255, y * 8 + x))
+            for x in range(40)
+        ]
+        for y in range(40)
+    ]
+    checker = [
+        [(255, 255, 255, 255) if (x + y) % 2 else (0, 0, 0, 255) for x in range(32)]
+        for y in range(32)
+    ]
+
+    write_png(HERE / "gradient.png", 48, 32, gradient)
+    write_png(HERE / "alpha.png", 40, 40, alpha)
+    write_png(HERE / "checker.png", 32, 32, checker)
+    write_png(
+        HERE / "metadata.png",
+        48,
+        32,
+        gradient,
+        extra_chunks=[
+            (b"pHYs", struct.pack(">IIB", 2835, 2835, 1)),
+            (b"tEXt", b"Comment\0Made somewhere private"),
+            (b"tIME", struct.pack(">HBBBBB", 2024, 5, 1, 10, 0, 0)),
+            (b"eXIf", b"MM\0*\0\0\0\x08\0\0\0\0\0\0"),
+        ],
+    )
+
+    if "--stand-in-references" not in sys.argv[1:]:
+        return
+
+    for name, pixels, height in [
+        ("gradient", gradient, 16),
+        ("gradient", gradient, 10),
+        ("alpha", alpha, 15),
+    ]:
+        width, thumbnail = reference(pixels, height)
+        write_png(HERE / f"{name}-{height}.png", width, height, thumbnail)
+
+
+if __name__ == "__main__":
+    main()